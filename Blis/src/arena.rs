@@ -0,0 +1,353 @@
+//! Hash-consed terms: structurally identical subterms are stored once and have the same id.
+//!
+//! The reducer works on the ids, terms are only converted from and to `Lambda` before and
+//! after the reduction. A substituted argument is referred to instead of copied at every use,
+//! terms that share most of their structure, like the steps of a reduction, take little
+//! memory, and comparing two stored terms is comparing their ids.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::Lambda;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct TermId(usize);
+
+/// A term whose children are stored in the arena.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Node {
+    Variable(usize),
+    Definition {
+        name_index: usize,
+        body: TermId,
+        parameter: Option<TermId>,
+    },
+    Call {
+        name_index: usize,
+        parameters: Vec<TermId>,
+    },
+}
+
+#[derive(Default)]
+pub(crate) struct Arena {
+    nodes: Vec<Node>,
+    /// the number of nodes of every term, counting shared subterms every time they occur
+    sizes: Vec<usize>,
+    /// the number of redexes of every term, counted like the nodes
+    redexes: Vec<usize>,
+    /// the free variables of every term, sorted
+    free: Vec<Box<[usize]>>,
+    ids: HashMap<Node, TermId>,
+}
+
+impl Arena {
+    fn intern(&mut self, node: Node) -> TermId {
+        if let Some(id) = self.ids.get(&node) {
+            return *id;
+        }
+        let (children, redexes, mut free) = match &node {
+            Node::Variable(value) => (0, 0, vec![*value]),
+            Node::Definition {
+                name_index,
+                body,
+                parameter,
+            } => {
+                let mut free: Vec<usize> = self.free(*body).to_vec();
+                free.retain(|name| name != name_index);
+                free.extend(parameter.map_or(&[][..], |p| self.free(p)));
+                (
+                    self.size(*body)
+                        .saturating_add(parameter.map_or(0, |p| self.size(p))),
+                    self.redexes(*body)
+                        .saturating_add(parameter.map_or(0, |p| self.redexes(p).saturating_add(1))),
+                    free,
+                )
+            }
+            Node::Call {
+                name_index,
+                parameters,
+            } => (
+                parameters
+                    .iter()
+                    .fold(0_usize, |size, p| size.saturating_add(self.size(*p))),
+                parameters
+                    .iter()
+                    .fold(0_usize, |count, p| count.saturating_add(self.redexes(*p))),
+                parameters
+                    .iter()
+                    .flat_map(|p| self.free(*p))
+                    .copied()
+                    .chain([*name_index])
+                    .collect(),
+            ),
+        };
+        free.sort_unstable();
+        free.dedup();
+        let id = TermId(self.nodes.len());
+        self.nodes.push(node.clone());
+        self.sizes.push(children.saturating_add(1));
+        self.redexes.push(redexes);
+        self.free.push(free.into());
+        self.ids.insert(node, id);
+        id
+    }
+
+    pub(crate) fn var(&mut self, value: usize) -> TermId {
+        self.intern(Node::Variable(value))
+    }
+
+    pub(crate) fn def(
+        &mut self,
+        name_index: usize,
+        body: TermId,
+        parameter: Option<TermId>,
+    ) -> TermId {
+        self.intern(Node::Definition {
+            name_index,
+            body,
+            parameter,
+        })
+    }
+
+    pub(crate) fn call(&mut self, name_index: usize, parameters: Vec<TermId>) -> TermId {
+        self.intern(Node::Call {
+            name_index,
+            parameters,
+        })
+    }
+
+    /// Stores `lambda`, returning the id that every term equal to it has.
+    pub(crate) fn insert(&mut self, lambda: &Lambda) -> TermId {
+        enum Work<'l> {
+            Visit(&'l Lambda),
+            Definition(usize, bool),
+            Call(usize, usize),
+        }
+        let mut work = vec![Work::Visit(lambda)];
+        let mut done = Vec::new();
+        while let Some(item) = work.pop() {
+            match item {
+                Work::Visit(Lambda::Variable(value)) => {
+                    done.push(self.intern(Node::Variable(*value)));
+                }
+                Work::Visit(Lambda::Definition {
+                    name_index,
+                    body,
+                    parameter,
+                }) => {
+                    work.push(Work::Definition(*name_index, parameter.is_some()));
+                    work.push(Work::Visit(body));
+                    work.extend(parameter.as_deref().map(Work::Visit));
+                }
+                Work::Visit(Lambda::Call {
+                    name_index,
+                    parameters,
+                }) => {
+                    work.push(Work::Call(*name_index, parameters.len()));
+                    work.extend(parameters.iter().rev().map(Work::Visit));
+                }
+                Work::Definition(name_index, has_parameter) => {
+                    let body = done.pop().unwrap();
+                    let parameter = has_parameter.then(|| done.pop().unwrap());
+                    done.push(self.intern(Node::Definition {
+                        name_index,
+                        body,
+                        parameter,
+                    }));
+                }
+                Work::Call(name_index, count) => {
+                    let parameters = done.split_off(done.len() - count);
+                    done.push(self.intern(Node::Call {
+                        name_index,
+                        parameters,
+                    }));
+                }
+            }
+        }
+        done.pop().unwrap()
+    }
+
+    /// The term `id`, with a copy of every shared subterm.
+    pub(crate) fn to_lambda(&self, id: TermId) -> Lambda {
+        enum Work {
+            Visit(TermId),
+            Definition(usize, bool),
+            Call(usize, usize),
+        }
+        let mut work = vec![Work::Visit(id)];
+        let mut done = Vec::new();
+        while let Some(item) = work.pop() {
+            match item {
+                Work::Visit(id) => match &self.nodes[id.0] {
+                    Node::Variable(value) => done.push(Lambda::var(*value)),
+                    Node::Definition {
+                        name_index,
+                        body,
+                        parameter,
+                    } => {
+                        work.push(Work::Definition(*name_index, parameter.is_some()));
+                        work.push(Work::Visit(*body));
+                        work.extend(parameter.map(Work::Visit));
+                    }
+                    Node::Call {
+                        name_index,
+                        parameters,
+                    } => {
+                        work.push(Work::Call(*name_index, parameters.len()));
+                        work.extend(parameters.iter().rev().copied().map(Work::Visit));
+                    }
+                },
+                Work::Definition(name_index, has_parameter) => {
+                    let body = done.pop().unwrap();
+                    let parameter = has_parameter.then(|| done.pop().unwrap());
+                    done.push(Lambda::def(name_index, body, parameter));
+                }
+                Work::Call(name_index, count) => {
+                    let parameters = done.split_off(done.len() - count);
+                    done.push(Lambda::call(name_index, parameters));
+                }
+            }
+        }
+        done.pop().unwrap()
+    }
+
+    /// The number of distinct terms stored.
+    pub(crate) fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Drops every term that is not part of `id`, which keeps its sharing. Returns the new id
+    /// of the term.
+    pub(crate) fn retain(&mut self, id: TermId) -> TermId {
+        let mut reachable = vec![id];
+        let mut stack = vec![id];
+        let mut seen = HashSet::from([id]);
+        while let Some(id) = stack.pop() {
+            let children = match &self.nodes[id.0] {
+                Node::Variable(_) => Vec::new(),
+                Node::Definition {
+                    body, parameter, ..
+                } => [*body].into_iter().chain(*parameter).collect(),
+                Node::Call { parameters, .. } => parameters.clone(),
+            };
+            for child in children {
+                if seen.insert(child) {
+                    reachable.push(child);
+                    stack.push(child);
+                }
+            }
+        }
+        // children are stored before their parents, so they are moved first
+        reachable.sort_unstable_by_key(|id| id.0);
+        let mut kept = Arena::default();
+        let mut ids = HashMap::new();
+        for old in reachable {
+            let node = match &self.nodes[old.0] {
+                Node::Variable(value) => Node::Variable(*value),
+                Node::Definition {
+                    name_index,
+                    body,
+                    parameter,
+                } => Node::Definition {
+                    name_index: *name_index,
+                    body: ids[body],
+                    parameter: parameter.map(|parameter| ids[&parameter]),
+                },
+                Node::Call {
+                    name_index,
+                    parameters,
+                } => Node::Call {
+                    name_index: *name_index,
+                    parameters: parameters.iter().map(|parameter| ids[parameter]).collect(),
+                },
+            };
+            ids.insert(old, kept.intern(node));
+        }
+        *self = kept;
+        ids[&id]
+    }
+
+    pub(crate) fn node(&self, id: TermId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    /// The number of nodes of the term `id` as a `Lambda`, saturating instead of overflowing.
+    pub(crate) fn size(&self, id: TermId) -> usize {
+        self.sizes[id.0]
+    }
+
+    /// The number of redexes of the term `id` as a `Lambda`, saturating like `size`.
+    pub(crate) fn redexes(&self, id: TermId) -> usize {
+        self.redexes[id.0]
+    }
+
+    /// The free variables of the term `id`, sorted.
+    pub(crate) fn free(&self, id: TermId) -> &[usize] {
+        &self.free[id.0]
+    }
+
+    pub(crate) fn is_free(&self, id: TermId, name: usize) -> bool {
+        self.free(id).binary_search(&name).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{generate::Generator, optimize::size, Lambda};
+
+    use super::{Arena, Node};
+
+    #[test]
+    fn equal_subterms_are_stored_once() {
+        let mut arena = Arena::default();
+        // x.(y.(z)).(y.(z))
+        let argument = Lambda::call(1, vec![Lambda::var(2)]);
+        let lambda = Lambda::call(0, vec![argument.clone(), argument.clone()]);
+        let id = arena.insert(&lambda);
+        assert_eq!(arena.nodes.len(), 3);
+        assert_eq!(arena.insert(&argument), arena.insert(&argument.clone()));
+        assert_eq!(arena.insert(&lambda.clone()), id);
+        assert_ne!(arena.insert(&argument), id);
+        assert_eq!(arena.size(id), 5);
+        assert_eq!(arena.to_lambda(id), lambda);
+    }
+
+    #[test]
+    fn random_terms_round_trip() {
+        let mut arena = Arena::default();
+        let mut generator = Generator::new(3, vec![0, 1, 2]);
+        for _ in 0..500 {
+            let lambda = generator.term();
+            let id = arena.insert(&lambda);
+            assert_eq!(arena.to_lambda(id), lambda);
+            assert_eq!(arena.size(id), size(&lambda));
+        }
+    }
+
+    #[test]
+    fn retained_terms_keep_their_sharing() {
+        let mut arena = Arena::default();
+        let argument = Lambda::call(1, vec![Lambda::var(2)]);
+        let lambda = Lambda::call(0, vec![argument.clone(), argument]);
+        arena.insert(&Lambda::def(3, Lambda::var(4), None));
+        let id = arena.insert(&lambda);
+        let id = arena.retain(id);
+        assert_eq!(arena.len(), 3);
+        assert_eq!(arena.to_lambda(id), lambda);
+        assert_eq!(arena.free(id), [0, 1, 2]);
+    }
+
+    #[test]
+    fn shared_subterms_are_not_copied() {
+        // x.(x.(…).(…)).(x.(…).(…)) with 2^100 variables as a tree
+        let mut arena = Arena::default();
+        let mut id = arena.intern(Node::Variable(0));
+        for _ in 0..100 {
+            id = arena.intern(Node::Call {
+                name_index: 0,
+                parameters: vec![id, id],
+            });
+        }
+        assert_eq!(arena.nodes.len(), 101);
+        assert_eq!(arena.size(id), usize::MAX);
+    }
+}
@@ -0,0 +1,196 @@
+//! `blis test`: checks the `assert` statements of programs.
+
+use std::{fs, path::Path};
+
+use crate::{
+    compiler::{compile_with_assertions, Assertion},
+    helpers::{alpha_equivalent, format_lambda_pretty},
+    parser::parse_program,
+    reducer::try_full_reduce,
+    Lambda, ITERATIONS,
+};
+
+pub(crate) const WIDTH: usize = 80;
+
+/// Reduces the compiled program `text`, failing instead of panicking if it has no normal form.
+fn normalize(text: &str) -> Result<(Lambda, Vec<String>), String> {
+    let (lambda, mut bindings) = parse_program(text)?;
+    let normal_form = try_full_reduce(lambda, &mut bindings, ITERATIONS)
+        .ok_or(format!("not reducible in {ITERATIONS} iterations"))?;
+    Ok((normal_form, bindings))
+}
+
+/// Compares `expected` and `actual` line by line, marking the lines that differ.
+pub(crate) fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let mut string = String::new();
+    for index in 0..expected.len().max(actual.len()) {
+        match (expected.get(index), actual.get(index)) {
+            (Some(expected), Some(actual)) if expected == actual => {
+                string += &format!("      {expected}\n");
+            }
+            (expected, actual) => {
+                if let Some(expected) = expected {
+                    string += &format!("    - {expected}\n");
+                }
+                if let Some(actual) = actual {
+                    string += &format!("    + {actual}\n");
+                }
+            }
+        }
+    }
+    string
+}
+
+/// Checks `assertion`, returning the difference of the normal forms if it fails.
+pub(crate) fn check(assertion: &Assertion) -> Result<(), String> {
+    let (actual, actual_bindings) = normalize(&assertion.left)?;
+    let (expected, expected_bindings) = if assertion.reduce_right {
+        normalize(&assertion.right)?
+    } else {
+        parse_program(&assertion.right)?
+    };
+    if alpha_equivalent(&actual, &actual_bindings, &expected, &expected_bindings) {
+        return Ok(());
+    }
+    Err(diff(
+        &format_lambda_pretty(&expected, &expected_bindings, WIDTH),
+        &format_lambda_pretty(&actual, &actual_bindings, WIDTH),
+    ))
+}
+
+/// The programs to test: the files given and the files in the directories given,
+/// except for the formats that cannot contain assertions and for snapshots.
+pub(crate) fn collect_files(paths: &[String]) -> Result<Vec<String>, String> {
+    let mut files = Vec::new();
+    for path in paths {
+        if !Path::new(path).is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+        let entries =
+            fs::read_dir(path).map_err(|error| format!("Error reading {path}: {error}"))?;
+        let mut directory = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|error| format!("Error reading {path}: {error}"))?;
+            let file = entry.path();
+            let extension = file.extension().and_then(|extension| extension.to_str());
+            if file.is_file() && !matches!(extension, Some("json" | "blc" | "expected")) {
+                directory.push(file.to_string_lossy().into_owned());
+            }
+        }
+        directory.sort();
+        files.extend(directory);
+    }
+    Ok(files)
+}
+
+/// Runs the assertions of the programs in `paths` and prints a summary.
+/// Returns whether all of them passed.
+pub(crate) fn run_tests(paths: &[String]) -> bool {
+    let files = match collect_files(paths) {
+        Ok(files) => files,
+        Err(error) => {
+            println!("{error}");
+            return false;
+        }
+    };
+    let (mut passed, mut failed) = (0, 0);
+    for file in files {
+        let assertions = fs::read_to_string(&file)
+            .map_err(|error| format!("Error reading file: {error}"))
+            .and_then(|contents| compile_with_assertions(&contents));
+        let assertions = match assertions {
+            Ok((_, assertions)) => assertions,
+            Err(error) => {
+                println!("{file}: FAILED\n    {error}");
+                failed += 1;
+                continue;
+            }
+        };
+        if assertions.is_empty() {
+            continue;
+        }
+        let failures: Vec<(usize, String)> = assertions
+            .iter()
+            .filter_map(|assertion| check(assertion).err().map(|diff| (assertion.line, diff)))
+            .collect();
+        passed += assertions.len() - failures.len();
+        failed += failures.len();
+        if failures.is_empty() {
+            println!("{file}: ok ({} assertions)", assertions.len());
+            continue;
+        }
+        println!("{file}: FAILED");
+        for (line, diff) in failures {
+            println!("  assertion at line {line}, expected (-) and actual (+):\n{diff}");
+        }
+    }
+    let result = if failed == 0 { "ok" } else { "FAILED" };
+    println!("test result: {result}. {passed} passed; {failed} failed");
+    failed == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::compile_with_assertions;
+
+    use super::{check, diff};
+
+    const NUMERALS: &str = "
+        let zero _f,x(x);
+        let succ n,f,x(f.(n.f.x));
+        let add m,n,f,x(m.f.(n.f.x));
+        let one succ.zero;
+        let two succ.one;
+    ";
+
+    fn results(source: &str) -> Vec<Result<(), String>> {
+        let (_, assertions) = compile_with_assertions(source).unwrap();
+        assertions.iter().map(check).collect()
+    }
+
+    #[test]
+    fn passing() {
+        let source = format!(
+            "{NUMERALS}
+            assert add.one.one == two;
+            assert_nf add.one.two == g,y(g.(g.(g.y)));
+            zero"
+        );
+        assert_eq!(results(&source), vec![Ok(()), Ok(())]);
+    }
+
+    #[test]
+    fn failing() {
+        let source = format!(
+            "{NUMERALS}
+            assert add.one.one == one;
+            assert_nf two == f,x(f.x);
+            zero"
+        );
+        assert_eq!(
+            results(&source),
+            vec![
+                Err("    - f(x(f.(x)))\n    + f(x(f.(f.(x))))\n".to_string()),
+                Err("    - f(x(f.(x)))\n    + f(x(f.(f.(x))))\n".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_assertion() {
+        assert!(compile_with_assertions("assert a; a").is_err());
+        let (_, assertions) = compile_with_assertions("\nassert x(x) == y(y);\nz(z)").unwrap();
+        assert_eq!(assertions[0].line, 2);
+    }
+
+    #[test]
+    fn diff_marks_changed_lines() {
+        assert_eq!(
+            diff("a\nb", "a\nc\nd"),
+            "      a\n    - b\n    + c\n    + d\n"
+        );
+    }
+}
@@ -0,0 +1,357 @@
+//! Tromp's Binary Lambda Calculus: `00 M` is an abstraction, `01 M N` an application
+//! and `1^i 0` the variable with de Bruijn index `i`, counted from 1.
+//! Free variables are encoded with indices pointing past the outermost binder.
+
+use std::collections::VecDeque;
+
+use crate::{reducer::insert_arguments, Lambda};
+
+fn encode(lambda: &Lambda, bits: &mut String) {
+    enum Work<'l> {
+        Visit(&'l Lambda),
+        /// the end of the body of a definition
+        Pop,
+    }
+    let mut scope = Vec::new();
+    let mut free = Vec::new();
+    let mut variable = |index: usize, scope: &Vec<usize>, bits: &mut String| {
+        let de_bruijn = match scope.iter().rposition(|i| *i == index) {
+            Some(position) => scope.len() - position,
+            None => {
+                let number = match free.iter().position(|i| *i == index) {
+                    Some(number) => number,
+                    None => {
+                        free.push(index);
+                        free.len() - 1
+                    }
+                };
+                scope.len() + number + 1
+            }
+        };
+        *bits += &"1".repeat(de_bruijn);
+        bits.push('0');
+    };
+    let mut work = vec![Work::Visit(lambda)];
+    while let Some(item) = work.pop() {
+        let lambda = match item {
+            Work::Visit(lambda) => lambda,
+            Work::Pop => {
+                scope.pop();
+                continue;
+            }
+        };
+        match lambda {
+            Lambda::Variable(value) => variable(*value, &scope, bits),
+            Lambda::Definition {
+                name_index,
+                body,
+                parameter,
+            } => {
+                if parameter.is_some() {
+                    *bits += "01";
+                }
+                *bits += "00";
+                scope.push(*name_index);
+                // the parameter is outside of the scope of the definition
+                work.extend(parameter.as_deref().map(Work::Visit));
+                work.push(Work::Pop);
+                work.push(Work::Visit(body));
+            }
+            Lambda::Call {
+                name_index,
+                parameters,
+            } => {
+                *bits += &"01".repeat(parameters.len());
+                variable(*name_index, &scope, bits);
+                work.extend(parameters.iter().rev().map(Work::Visit));
+            }
+        }
+    }
+}
+
+/// Encodes `lambda` as a string of `0`s and `1`s.
+pub(crate) fn lambda_to_blc(lambda: &Lambda) -> String {
+    let mut bits = String::new();
+    encode(lambda, &mut bits);
+    bits
+}
+
+/// Packs a bit string into bytes, most significant bit first, padding the last byte with zeros.
+pub(crate) fn bits_to_bytes(bits: &str) -> Vec<u8> {
+    bits.as_bytes()
+        .chunks(8)
+        .map(|chunk| {
+            let byte = chunk
+                .iter()
+                .fold(0u8, |byte, bit| (byte << 1) | (*bit == b'1') as u8);
+            byte << (8 - chunk.len())
+        })
+        .collect()
+}
+
+/// Reads either a textual bit string or packed bytes.
+pub(crate) fn read_bits(contents: &[u8]) -> String {
+    let is_text = contents
+        .iter()
+        .all(|byte| *byte == b'0' || *byte == b'1' || byte.is_ascii_whitespace());
+    if is_text {
+        contents
+            .iter()
+            .filter(|byte| !byte.is_ascii_whitespace())
+            .map(|byte| *byte as char)
+            .collect()
+    } else {
+        contents.iter().map(|byte| format!("{byte:08b}")).collect()
+    }
+}
+
+/// A decoded term, which refers to its subterms by their position in the list of all terms.
+enum Term {
+    Variable(usize),
+    Abstraction(usize),
+    Application(usize, usize),
+}
+
+/// Decodes the term at the start of `bits` into `terms`, returning its position there.
+fn decode(bits: &[u8], position: &mut usize, terms: &mut Vec<Term>) -> Result<usize, String> {
+    enum Pending {
+        Abstraction,
+        Function,
+        /// the argument of an application with the given function
+        Argument(usize),
+    }
+    let mut next = || {
+        let bit = bits
+            .get(*position)
+            .ok_or_else(|| format!("unexpected end of input after {} bits", bits.len()))?;
+        *position += 1;
+        Ok::<_, String>(*bit == b'1')
+    };
+    let mut pending = Vec::new();
+    loop {
+        let mut term = if next()? {
+            let mut index = 1;
+            while next()? {
+                index += 1;
+            }
+            Term::Variable(index)
+        } else {
+            pending.push(if next()? {
+                Pending::Function
+            } else {
+                Pending::Abstraction
+            });
+            continue;
+        };
+        // finish the terms that were waiting for this one
+        loop {
+            terms.push(term);
+            let id = terms.len() - 1;
+            match pending.pop() {
+                None => return Ok(id),
+                Some(Pending::Abstraction) => term = Term::Abstraction(id),
+                Some(Pending::Argument(function)) => term = Term::Application(function, id),
+                Some(Pending::Function) => {
+                    pending.push(Pending::Argument(id));
+                    break;
+                }
+            }
+        }
+    }
+}
+
+struct Converter<'t> {
+    terms: &'t [Term],
+    bindings: Vec<String>,
+    scope: Vec<usize>,
+    free: Vec<usize>,
+}
+
+impl Converter<'_> {
+    fn variable(&mut self, de_bruijn: usize) -> usize {
+        if de_bruijn <= self.scope.len() {
+            return self.scope[self.scope.len() - de_bruijn];
+        }
+        let number = de_bruijn - self.scope.len() - 1;
+        while self.free.len() <= number {
+            self.free.push(self.bindings.len());
+            self.bindings.push(format!("free{}", self.free.len()));
+        }
+        self.free[number]
+    }
+
+    /// Converts the term `root`, with the arguments of an application before its function.
+    fn convert(&mut self, root: usize) -> Lambda {
+        enum Work {
+            Visit(usize),
+            /// the function of an application once its arguments are converted
+            Head(usize, usize),
+            /// the end of the body of an abstraction applied to the given number of arguments
+            Abstraction(usize, usize),
+        }
+        let mut work = vec![Work::Visit(root)];
+        let mut done: Vec<Lambda> = Vec::new();
+        while let Some(item) = work.pop() {
+            match item {
+                Work::Visit(id) => {
+                    let mut arguments = Vec::new();
+                    let mut head = id;
+                    while let Term::Application(function, argument) = self.terms[head] {
+                        arguments.push(argument);
+                        head = function;
+                    }
+                    work.push(Work::Head(head, arguments.len()));
+                    // `arguments` is in reverse, so the first one is visited first
+                    work.extend(arguments.into_iter().map(Work::Visit));
+                }
+                Work::Head(head, count) => match self.terms[head] {
+                    Term::Variable(de_bruijn) => {
+                        let mut arguments = done.split_off(done.len() - count).into();
+                        let mut lambda = Lambda::var(self.variable(de_bruijn));
+                        insert_arguments(&mut lambda, &mut arguments);
+                        done.push(lambda);
+                    }
+                    Term::Abstraction(body) => {
+                        let name_index = self.bindings.len();
+                        let letter = (b'a' + (self.scope.len() % 26) as u8) as char;
+                        self.bindings.push(letter.to_string());
+                        self.scope.push(name_index);
+                        work.push(Work::Abstraction(name_index, count));
+                        work.push(Work::Visit(body));
+                    }
+                    Term::Application(..) => unreachable!(),
+                },
+                Work::Abstraction(name_index, count) => {
+                    self.scope.pop();
+                    let body = done.pop().unwrap();
+                    let mut arguments: VecDeque<Lambda> = done.split_off(done.len() - count).into();
+                    let mut lambda = Lambda::def(name_index, body, None);
+                    insert_arguments(&mut lambda, &mut arguments);
+                    done.push(lambda);
+                }
+            }
+        }
+        done.pop().unwrap()
+    }
+}
+
+/// Decodes a bit string into a term. Binders are named by their depth, free variables
+/// `free1`, `free2`, ... in the order of their de Bruijn index.
+pub(crate) fn blc_to_lambda(bits: &str) -> Result<(Lambda, Vec<String>), String> {
+    if let Some(invalid) = bits.chars().find(|c| *c != '0' && *c != '1') {
+        return Err(format!("unexpected character {invalid:?}, expected 0 or 1"));
+    }
+    let bits = bits.as_bytes();
+    let mut position = 0;
+    let mut terms = Vec::new();
+    let root = decode(bits, &mut position, &mut terms)?;
+    let rest = &bits[position..];
+    // packed bytes are padded with up to 7 zeros
+    if rest.len() >= 8 || rest.contains(&b'1') {
+        return Err(format!(
+            "unexpected trailing bits after the term ended at bit {position}"
+        ));
+    }
+    let mut converter = Converter {
+        terms: &terms,
+        bindings: Vec::new(),
+        scope: Vec::new(),
+        free: Vec::new(),
+    };
+    let lambda = converter.convert(root);
+    Ok((lambda, converter.bindings))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        compiler::compile, helpers::alpha_equivalent, parser::parse_program, reducer::full_reduce,
+    };
+
+    use super::{bits_to_bytes, blc_to_lambda, lambda_to_blc, read_bits};
+
+    fn round_trip(text: &str) -> String {
+        let (lambda, bindings) = parse_program(&compile(text).unwrap()).unwrap();
+        let bits = lambda_to_blc(&lambda);
+        let (decoded, decoded_bindings) = blc_to_lambda(&bits).unwrap();
+        assert!(alpha_equivalent(
+            &lambda,
+            &bindings,
+            &decoded,
+            &decoded_bindings
+        ));
+        bits
+    }
+
+    #[test]
+    fn identity() {
+        assert_eq!(round_trip("x(x)"), "0010");
+    }
+
+    #[test]
+    fn church_two() {
+        assert_eq!(round_trip("f(x(f.(f.x)))"), "0000011100111010");
+    }
+
+    #[test]
+    fn applied_definitions() {
+        // the second argument is stored as parameter of the inner definition: (λa.(λb.a b) d) c
+        assert_eq!(
+            round_trip("a(b(a.b)).c(c).d(d)"),
+            concat!("01", "00", "01", "00", "01", "110", "10", "0010", "0010")
+        );
+        // (λa.λb.a b) c d
+        let (decoded, decoded_bindings) = blc_to_lambda(concat!(
+            "01", "01", "00", "00", "01", "110", "10", "0010", "0010"
+        ))
+        .unwrap();
+        let (lambda, bindings) = parse_program("a(b(a.b)).c(c).d(d)").unwrap();
+        assert!(alpha_equivalent(
+            &lambda,
+            &bindings,
+            &decoded,
+            &decoded_bindings
+        ));
+    }
+
+    #[test]
+    fn free_variables() {
+        let (lambda, _bindings) = parse_program("a(a.(y)).(z)").unwrap();
+        let bits = lambda_to_blc(&lambda);
+        assert_eq!(bits, concat!("01", "00", "01", "10", "110", "110"));
+        let (_, decoded_bindings) = blc_to_lambda(&bits).unwrap();
+        assert_eq!(decoded_bindings, vec!["free1", "free2", "a"]);
+    }
+
+    #[test]
+    fn packed_bytes() {
+        let bits = "000001110011100111010";
+        let bytes = bits_to_bytes(bits);
+        assert_eq!(bytes.len(), 3);
+        let unpacked = read_bits(&bytes);
+        assert_eq!(unpacked.len(), 24);
+        assert!(blc_to_lambda(&unpacked).is_ok());
+        assert_eq!(read_bits(b"0010\n"), "0010");
+    }
+
+    #[test]
+    fn reduce_decoded_program() {
+        // (λx.x) (λy.y)
+        let (lambda, mut bindings) = blc_to_lambda("0100100010").unwrap();
+        let reduced = full_reduce(lambda, &mut bindings, 10);
+        let (expected, expected_bindings) = parse_program("y(y)").unwrap();
+        assert!(alpha_equivalent(
+            &reduced,
+            &bindings,
+            &expected,
+            &expected_bindings
+        ));
+    }
+
+    #[test]
+    fn invalid_input() {
+        assert!(blc_to_lambda("00").is_err());
+        assert!(blc_to_lambda("0010 1").is_err());
+        assert!(blc_to_lambda("00101").is_err());
+    }
+}
@@ -1,68 +1,878 @@
+use std::collections::{HashMap, HashSet};
+
 use regex::Regex;
 
-use crate::{helpers::find_block_end, parser::remove_whitespace};
+use crate::{
+    data::{constructor_lets, parse_data, replace_matches, DataType},
+    helpers::{block_ends, find_block_end, fresh_name},
+    literals::replace_literals,
+    operators::{parse_operator, replace_operators, Operator},
+    parser::{marked, parse_program, remove_whitespace, split_mark, strip_marks},
+    types::{check_annotations, parse_type, parse_type_prefix, Annotation, Type},
+    Lambda,
+};
 
-fn replace_lets(text: &str) -> String {
-    let mut lets = Vec::new();
+struct Let {
+    name: String,
+    /// the offset of the name in the source, if it is written there
+    span: Option<usize>,
+    body: String,
+    annotation: Option<Annotation>,
+}
+
+/// An `assert left == right;` statement, with both sides compiled in the scope of the lets.
+/// For `assert_nf`, the right side is the expected normal form and is not reduced.
+pub(crate) struct Assertion {
+    pub(crate) line: usize,
+    pub(crate) left: String,
+    pub(crate) right: String,
+    pub(crate) reduce_right: bool,
+    /// the names mentioned in the statement
+    pub(crate) names: HashSet<String>,
+}
+
+/// The declarations of a program, which apply to all of its definitions.
+#[derive(Default)]
+struct Declarations {
+    aliases: HashMap<String, String>,
+    operators: Vec<Operator>,
+    data: Vec<DataType>,
+}
+
+impl Declarations {
+    /// Lowers matches, local lets and operators.
+    fn desugar(&self, text: &str) -> Result<String, String> {
+        let text = replace_matches(text, &self.data)?;
+        replace_operators(&replace_local_lets(&text)?, &self.operators)
+    }
+}
+
+/// The names used in `program`, roughly: everything between the characters of the syntax.
+fn identifiers(program: &str) -> HashSet<&str> {
+    program
+        .split(|c: char| "().,;:=".contains(c) || c.is_whitespace())
+        .map(|name| split_mark(name).0)
+        .collect()
+}
+
+/// The length of the character or string literal at the start of `text`.
+fn literal_length(text: &str) -> usize {
+    let quote = text.chars().next().unwrap();
+    let mut chars = text.char_indices().skip(1);
+    while let Some((index, c)) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if c == quote {
+            return index + 1;
+        }
+    }
+    text.len()
+}
+
+/// The length of the type at the start of `text`, which follows a `:`. The type ends
+/// before `=`, `,` or the `(` of a body.
+fn type_length(text: &str) -> usize {
+    let mut depth = 0;
+    let mut expects_type = true;
     let mut index = 0;
-    while text[index..].trim().starts_with("let") {
-        let text = &text[index..];
-        let mut s = text.trim().split(';').next().unwrap().split(' ');
-        let _ = s.next(); // skip let
-        let name = s.next().unwrap();
-        let body: String = s.collect();
-        lets.push((name, body));
-        index += text
-            .find(';')
-            .expect("Expect semicolon after let-definition")
-            + 1;
+    while let Some(c) = text[index..].chars().next() {
+        if text[index..].starts_with("->") {
+            expects_type = true;
+            index += 2;
+            continue;
+        }
+        match c {
+            '(' if expects_type => depth += 1,
+            ')' if depth > 0 => {
+                depth -= 1;
+                expects_type = false;
+            }
+            c if c.is_whitespace() => {}
+            c if c.is_alphanumeric() || c == '_' || c == '\'' => expects_type = false,
+            _ if depth == 0 => break,
+            _ => {}
+        }
+        index += c.len_utf8();
     }
-    let text = &text[index..];
-    let mut string = String::from(text);
-    for (name, body) in lets.into_iter().rev() {
-        string = format!("{name}({string}).({body})");
+    index
+}
+
+/// Marks the binders written in `text` with their offset, see `marked`: the names after
+/// `let`, `letrec` and `and`, and parameters, which are followed by `(`, `,` or a type.
+/// Literals, types and declarations have no binders and are copied as they are.
+fn mark_binders(text: &str) -> String {
+    const KEYWORDS: [&str; 7] = ["let", "letrec", "and", "in", "match", "assert", "assert_nf"];
+    const DECLARATIONS: [&str; 5] = ["type", "data", "infixl", "infixr", "infix"];
+    let is_name_char = |c: char| !"().,;:={}\"".contains(c) && !c.is_whitespace();
+    let mut string = String::with_capacity(text.len());
+    let mut index = 0;
+    let mut depth = 0;
+    let mut statement_start = true;
+    // the name before the current one, if only whitespace is between them
+    let mut previous = "";
+    while let Some(c) = text[index..].chars().next() {
+        let rest = &text[index..];
+        // a quote after a name is part of it, like in `x'`
+        let length = if c == '"' || (c == '\'' && !string.ends_with(is_name_char)) {
+            previous = "";
+            literal_length(rest)
+        } else if c == ':' {
+            previous = "";
+            1 + type_length(&rest[1..])
+        } else if is_name_char(c) {
+            let length = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+            let name = &rest[..length];
+            if statement_start && DECLARATIONS.contains(&name) {
+                find_statement_end(rest).unwrap_or(rest.len())
+            } else {
+                let after = rest[length..].trim_start();
+                let is_binder = !KEYWORDS.contains(&name)
+                    && name.starts_with(|c: char| c.is_alphanumeric() || c == '_')
+                    && (matches!(previous, "let" | "letrec" | "and")
+                        || after.starts_with(['(', ',', ':']));
+                string += &marked(name, is_binder.then_some(index));
+                index += length;
+                statement_start = false;
+                previous = name;
+                continue;
+            }
+        } else {
+            match c {
+                '(' | '{' => depth += 1,
+                ')' | '}' => depth -= 1,
+                ';' if depth == 0 => statement_start = true,
+                _ => {}
+            }
+            if !c.is_whitespace() {
+                previous = "";
+            }
+            c.len_utf8()
+        };
+        if !c.is_whitespace() && c != ';' {
+            statement_start = false;
+        }
+        string += &rest[..length];
+        index += length;
     }
     string
 }
 
-pub(crate) fn compile(text: &str) -> String {
-    let after_lets_replaced = replace_lets(text);
-    let without_whitespace = remove_whitespace(&after_lets_replaced);
-    let compiled = replace_comma_definition(without_whitespace);
-    assert!(find_block_end(&compiled).is_some());
-    compiled
+/// Removes the annotations from the parameter list at the start of a let body,
+/// e.g. `n:Nat,f,x(...)`, and returns the body with the parameter types.
+fn strip_parameter_annotations(
+    body: &str,
+    aliases: &HashMap<String, String>,
+    names: &mut Vec<Option<String>>,
+) -> Result<(String, Vec<Option<Type>>), String> {
+    let mut parameters = Vec::new();
+    let mut types = Vec::new();
+    let mut rest = body.trim_start();
+    loop {
+        let name_end = rest
+            .find(|c: char| "(),.:;".contains(c) || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let name = &rest[..name_end];
+        rest = rest[name_end..].trim_start();
+        if name.is_empty() {
+            return Ok((body.to_owned(), Vec::new()));
+        }
+        let t = if let Some(annotation) = rest.strip_prefix(':') {
+            let (t, end) = parse_type_prefix(annotation, aliases, names)?;
+            rest = annotation[end..].trim_start();
+            Some(t)
+        } else {
+            None
+        };
+        if t.is_none() && !rest.starts_with(',') && !rest.starts_with('(') {
+            // not a parameter list, e.g. `succ.(succ.zero)`
+            return Ok((body.to_owned(), Vec::new()));
+        }
+        parameters.push(name);
+        types.push(t);
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after.trim_start();
+        } else if rest.starts_with('(') {
+            return Ok((format!("{}{rest}", parameters.join(",")), types));
+        } else {
+            return Err(format!(
+                "expected `,` or `(` after the parameter `{name}` of a let definition"
+            ));
+        }
+    }
 }
 
-fn replace_comma_definition(mut text: String) -> String {
-    let regex = Regex::new("[^(),.]+(,[^(),.]+)+").unwrap();
+/// Parses `name body` or `name : type = body` following a `let`,
+/// both with optional parameter annotations.
+fn parse_let(statement: &str, declarations: &Declarations) -> Result<Let, String> {
+    let aliases = &declarations.aliases;
+    let statement = statement.trim_start();
+    let name_end = statement
+        .find(|c: char| c == ':' || c.is_whitespace())
+        .unwrap_or(statement.len());
+    let (name, span) = split_mark(&statement[..name_end]);
+    let rest = statement[name_end..].trim_start();
+    let mut names = Vec::new();
+    let mut types = Vec::new();
+    let body = if let Some(annotation) = rest.strip_prefix(':') {
+        let equals = annotation
+            .find('=')
+            .ok_or_else(|| format!("expected `=` after the type of `{name}`"))?;
+        types.push(parse_type(&annotation[..equals], aliases, &mut names)?);
+        &annotation[equals + 1..]
+    } else if let Some(body) = rest.strip_prefix('=') {
+        body
+    } else {
+        rest
+    };
+    let (body, parameter_types) = strip_parameter_annotations(body, aliases, &mut names)?;
+    if parameter_types.iter().any(Option::is_some) {
+        let mut placeholder = || {
+            names.push(None);
+            Type::Variable(names.len() - 1)
+        };
+        let mut t = placeholder();
+        for parameter in parameter_types.into_iter().rev() {
+            let parameter = parameter.unwrap_or_else(&mut placeholder);
+            t = Type::function(parameter, t);
+        }
+        types.push(t);
+    }
+    let annotation = (!types.is_empty()).then(|| Annotation {
+        definition: name.to_owned(),
+        types,
+        names,
+    });
+    Ok(Let {
+        name: name.to_owned(),
+        span,
+        body: declarations.desugar(&body)?,
+        annotation,
+    })
+}
+
+/// Desugars `letrec f ... and g ...` into lets using the fixpoint combinator
+/// `Y = f(x(f.(x.x)).(x(f.(x.x))))`. A group of mutually recursive definitions is the
+/// fixpoint of a function returning all of them as a tuple, from which they are selected.
+fn parse_letrec(
+    group: &str,
+    declarations: &Declarations,
+    program: &str,
+) -> Result<Vec<Let>, String> {
+    let definitions = split_at_keyword(group, "and")
+        .into_iter()
+        .map(|definition| parse_let(definition, declarations))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(definition) = definitions.iter().find(|d| d.annotation.is_some()) {
+        return Err(format!(
+            "recursive definition `{}` cannot have a type annotation",
+            definition.name
+        ));
+    }
+    let identifiers = identifiers(program);
+    let mut used = Vec::new();
+    let mut fresh = |base: &str| {
+        let name = fresh_name(base, |name| {
+            identifiers.contains(name) || used.iter().any(|used| used == name)
+        });
+        used.push(name.clone());
+        name
+    };
+    let (f, x) = (fresh("f"), fresh("x"));
+    let fix = format!("{f}({x}({f}.({x}.{x})).({x}({f}.({x}.{x}))))");
+    if let [Let {
+        name, span, body, ..
+    }] = definitions.as_slice()
+    {
+        return Ok(vec![Let {
+            name: name.clone(),
+            span: *span,
+            body: format!("{fix}.({name}({body}))"),
+            annotation: None,
+        }]);
+    }
+    let (tuple, select) = (fresh("rec"), fresh("select"));
+    let parameters: Vec<String> = definitions.iter().map(|_| fresh("x")).collect();
+    let selectors: Vec<String> = parameters
+        .iter()
+        .map(|parameter| format!("{}({parameter})", parameters.join(",")))
+        .collect();
+    let bodies: Vec<String> = definitions
+        .iter()
+        .map(|Let { body, .. }| {
+            let mut string = body.clone();
+            for (definition, selector) in definitions.iter().zip(&selectors).rev() {
+                string = format!("{}({string}).({tuple}.({selector}))", definition.name);
+            }
+            format!(".({string})")
+        })
+        .collect();
+    let mut lets = vec![Let {
+        name: tuple.clone(),
+        span: None,
+        body: format!("{fix}.({tuple}({select}({select}{})))", bodies.concat()),
+        annotation: None,
+    }];
+    for (definition, selector) in definitions.iter().zip(&selectors) {
+        lets.push(Let {
+            name: definition.name.clone(),
+            span: definition.span,
+            body: format!("{tuple}.({selector})"),
+            annotation: None,
+        });
+    }
+    Ok(lets)
+}
+
+/// Collects the names that `text` uses without binding them, in the order the parser
+/// resolves them, and whether they are called. Like in the parser, a free variable is in
+/// scope for the rest of the term.
+fn collect_free_names<'a>(text: &'a str, free: &mut Vec<(&'a str, bool)>) {
+    enum Work<'a> {
+        /// the term at this range of `text`
+        Visit(usize, usize),
+        /// a definition, visited after the arguments it is called with
+        Definition(&'a str, usize, usize),
+        /// a variable, visited after its arguments, and whether it has any
+        Variable(&'a str, bool),
+        /// the end of the body of a definition
+        Unbind(&'a str),
+    }
+    let ends = block_ends(text);
+    let block_end = |start: usize| ends.get(&start).copied();
+    let mut scope: Vec<&str> = Vec::new();
+    let mut work = vec![Work::Visit(0, text.len())];
+    while let Some(item) = work.pop() {
+        let (mut start, end) = match item {
+            Work::Visit(start, end) => (start, end),
+            Work::Definition(name, body_start, body_end) => {
+                scope.push(name);
+                work.push(Work::Unbind(name));
+                work.push(Work::Visit(body_start, body_end));
+                continue;
+            }
+            Work::Variable(name, is_call) => {
+                if !name.is_empty() && !scope.contains(&name) {
+                    free.push((name, is_call));
+                    if !is_call {
+                        scope.push(name);
+                    }
+                }
+                continue;
+            }
+            Work::Unbind(name) => {
+                let position = scope.iter().rposition(|bound| *bound == name).unwrap();
+                scope.remove(position);
+                continue;
+            }
+        };
+        let head = if text[start..end].starts_with('(') {
+            let Some(block_end) = block_end(start) else {
+                continue;
+            };
+            let block = Work::Visit(start + 1, block_end);
+            start = block_end + 1;
+            block
+        } else {
+            let name_end = text[start..end]
+                .find(['(', '.'])
+                .map_or(end, |offset| start + offset);
+            let name = &text[start..name_end];
+            if text[name_end..end].starts_with('(') {
+                let Some(block_end) = block_end(name_end) else {
+                    continue;
+                };
+                let definition = Work::Definition(split_mark(name).0, name_end + 1, block_end);
+                start = block_end + 1;
+                definition
+            } else {
+                start = name_end;
+                Work::Variable(name, text[start..end].starts_with('.'))
+            }
+        };
+        let mut arguments = Vec::new();
+        while text[start..end].starts_with('.') {
+            start += 1;
+            let argument_end = match text[start..end].find(['(', '.']) {
+                Some(offset) if text[start + offset..].starts_with('(') => {
+                    block_end(start + offset).map_or(end, |block_end| block_end + 1)
+                }
+                Some(offset) => start + offset,
+                None => end,
+            };
+            arguments.push(Work::Visit(start, argument_end));
+            start = argument_end;
+        }
+        // the arguments come first, in order
+        work.push(head);
+        work.extend(arguments.into_iter().rev());
+    }
+}
+
+/// Returns the positions in `names` of the top level definitions that `body` refers to.
+fn dependencies(body: &str, names: &[&str]) -> Result<Vec<usize>, String> {
+    let body = replace_comma_definition(&remove_whitespace(&replace_local_lets(body)?))?;
+    let mut free = Vec::new();
+    collect_free_names(&body, &mut free);
+    Ok(names
+        .iter()
+        .enumerate()
+        .filter(|(_, name)| free.iter().any(|(used, _)| used == *name))
+        .map(|(index, _)| index)
+        .collect())
+}
 
+/// Orders the lets so that every definition comes after the ones it refers to,
+/// keeping the order of the file where possible.
+fn order_lets(lets: Vec<Let>) -> Result<Vec<Let>, String> {
+    let names: Vec<&str> = lets
+        .iter()
+        .map(|definition| definition.name.as_str())
+        .collect();
+    let dependencies = lets
+        .iter()
+        .map(|definition| dependencies(&definition.body, &names))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut order = Vec::new();
+    while order.len() < lets.len() {
+        let is_ready = |index: &usize| {
+            !order.contains(index)
+                && dependencies[*index]
+                    .iter()
+                    .all(|dependency| order.contains(dependency))
+        };
+        if let Some(next) = (0..lets.len()).find(is_ready) {
+            order.push(next);
+            continue;
+        }
+        // every remaining definition waits for another one, so following them finds a cycle
+        let mut cycle = vec![(0..lets.len()).find(|i| !order.contains(i)).unwrap()];
+        loop {
+            let last = cycle[cycle.len() - 1];
+            let next = *dependencies[last]
+                .iter()
+                .find(|dependency| !order.contains(*dependency))
+                .unwrap();
+            if let Some(start) = cycle.iter().position(|index| *index == next) {
+                cycle.drain(..start);
+                cycle.push(next);
+                break;
+            }
+            cycle.push(next);
+        }
+        let cycle: Vec<String> = cycle
+            .iter()
+            .map(|index| format!("`{}`", names[*index]))
+            .collect();
+        return Err(format!(
+            "cyclic definitions {}, use letrec for recursion",
+            cycle.join(" -> ")
+        ));
+    }
+    let mut lets: Vec<Option<Let>> = lets.into_iter().map(Some).collect();
+    Ok(order
+        .into_iter()
+        .map(|index| lets[index].take().unwrap())
+        .collect())
+}
+
+/// Returns the line of the first occurrence of the name `name` in `text`, counted from 1.
+fn line_of(text: &str, name: &str) -> usize {
+    let is_name = |c: char| !"().,;:=".contains(c) && !c.is_whitespace();
+    let position = text
+        .match_indices(name)
+        .find(|(index, _)| {
+            !text[..*index].ends_with(is_name) && !text[index + name.len()..].starts_with(is_name)
+        })
+        .map_or(0, |(index, _)| index);
+    text[..position].matches('\n').count() + 1
+}
+
+fn replace_lets(text: &str) -> Result<(String, Vec<Let>, Vec<Assertion>), String> {
+    let mut statements = Vec::new();
+    let mut index = 0;
     loop {
-        let mut string = String::new();
-        let find = regex.find(&text);
-        if find.is_none() {
-            return text;
+        let statement_start = text[index..].trim_start();
+        let keywords = [
+            "type ",
+            "data ",
+            "infixl ",
+            "infixr ",
+            "infix ",
+            "letrec ",
+            "let ",
+            "assert ",
+            "assert_nf ",
+        ];
+        let Some(keyword) = keywords
+            .into_iter()
+            .find(|keyword| statement_start.starts_with(keyword))
+        else {
+            break;
+        };
+        if keyword == "let " && split_local_let(statement_start)?.is_some() {
+            // `let x = e in body` is an expression, which is handled by `replace_local_lets`
+            break;
+        }
+        let length = find_statement_end(statement_start).ok_or_else(|| {
+            if keyword.starts_with("let") {
+                "Expect semicolon after let-definition".to_string()
+            } else {
+                format!("Expect semicolon after {}declaration", keyword)
+            }
+        })?;
+        let line = text[..text.len() - statement_start.len()]
+            .matches('\n')
+            .count()
+            + 1;
+        statements.push((keyword.trim_end(), &statement_start[..length], line));
+        index = text.len() - statement_start.len() + length + 1;
+    }
+    // declarations apply to the whole program, so they are read before the definitions
+    let mut declarations = Declarations::default();
+    for (keyword, statement, _) in &statements {
+        let rest = &statement[keyword.len()..];
+        match *keyword {
+            "type" => {
+                let (name, definition) =
+                    rest.split_once('=').ok_or("expected `=` in type alias")?;
+                // aliases can only refer to earlier aliases, so they cannot be recursive
+                parse_type(definition, &declarations.aliases, &mut Vec::new())?;
+                let name = name.trim().to_owned();
+                declarations
+                    .aliases
+                    .insert(name, definition.trim().to_owned());
+            }
+            "data" => declarations.data.push(parse_data(rest)?),
+            _ => {
+                if let Some(operator) = parse_operator(statement) {
+                    declarations.operators.push(operator?);
+                }
+            }
+        }
+    }
+    let mut lets = Vec::new();
+    let taken = identifiers(text);
+    for data in &declarations.data {
+        let constructors = constructor_lets(data, |name| taken.contains(name));
+        lets.extend(constructors.into_iter().map(|(name, body)| Let {
+            name,
+            span: None,
+            body,
+            annotation: None,
+        }));
+    }
+    for (keyword, statement, _) in &statements {
+        let rest = &statement[keyword.len()..];
+        match *keyword {
+            "letrec" => lets.extend(parse_letrec(rest, &declarations, text)?),
+            "let" => lets.push(parse_let(rest, &declarations)?),
+            _ => {}
         }
-        let find = find.unwrap();
-        let start = find.start();
-        string += &text[..start];
-        let args_end = find.end();
-        let args = &text[start..args_end].split(',').collect::<Vec<&str>>();
-        let number_of_args = args.len();
-        let body_end = args_end + find_block_end(&text[args_end..]).unwrap();
-        let rest = &text[body_end + 1..];
+    }
+    let lets = order_lets(lets)?;
+    let in_scope_of_lets = |expression: &str| {
+        let mut string = declarations.desugar(expression)?;
+        for Let {
+            name, span, body, ..
+        } in lets.iter().rev()
+        {
+            string = format!("{}({string}).({body})", marked(name, *span));
+        }
+        Ok::<_, String>(string)
+    };
+    let mut assertions = Vec::new();
+    for (keyword, statement, line) in &statements {
+        let reduce_right = match *keyword {
+            "assert" => true,
+            "assert_nf" => false,
+            _ => continue,
+        };
+        let rest = &statement[keyword.len()..];
+        let [left, right] = split_at_keyword(rest, "==")[..] else {
+            return Err(format!(
+                "expected `{keyword} <term> == <expected>` at line {line}"
+            ));
+        };
+        // the expected normal form is compared as it is written, without the lets
+        let right = if reduce_right {
+            in_scope_of_lets(right)?
+        } else {
+            declarations.desugar(right)?
+        };
+        assertions.push(Assertion {
+            line: *line,
+            left: in_scope_of_lets(left)?,
+            right,
+            reduce_right,
+            names: identifiers(rest).into_iter().map(str::to_owned).collect(),
+        });
+    }
+    let string = in_scope_of_lets(&text[index..])?;
+    Ok((string, lets, assertions))
+}
+
+fn is_keyword_at(text: &str, index: usize, keyword: &str) -> bool {
+    if !text[index..].starts_with(keyword) {
+        return false;
+    }
+    let before = text[..index].chars().next_back();
+    let after = text[index + keyword.len()..].chars().next();
+    before.is_none_or(|c| c.is_whitespace() || c == '(' || c == ')')
+        && after.map_or(keyword == "in", |c| {
+            c.is_whitespace() || (keyword == "in" && c == '(')
+        })
+}
+
+/// Splits `text` at every `keyword` outside of parentheses.
+fn split_at_keyword<'a>(text: &'a str, keyword: &str) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (index, c) in text.char_indices() {
+        match c {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth -= 1,
+            _ if depth == 0 && is_keyword_at(text, index, keyword) => {
+                parts.push(&text[start..index]);
+                start = index + keyword.len();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// Finds the first `;` outside of parentheses.
+fn find_statement_end(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (index, c) in text.char_indices() {
+        match c {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth -= 1,
+            ';' if depth == 0 => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Finds the `in` belonging to a `let x = e in body`, given the text after `=`.
+fn find_in_keyword(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut nested_lets = 0;
+    for (index, c) in text.char_indices() {
+        match c {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth -= 1,
+            ';' if depth == 0 => return None,
+            _ if depth == 0 && is_keyword_at(text, index, "let") => nested_lets += 1,
+            _ if depth == 0 && is_keyword_at(text, index, "in") => {
+                if nested_lets == 0 {
+                    return Some(index);
+                }
+                nested_lets -= 1;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits `let x = e in body` into name, value and body.
+fn split_local_let(text: &str) -> Result<Option<(&str, &str, &str)>, String> {
+    let after_let = text["let".len()..].trim_start();
+    let name_end = after_let
+        .find(|c: char| c == '=' || c.is_whitespace())
+        .unwrap_or(after_let.len());
+    let name = &after_let[..name_end];
+    let Some(value) = after_let[name_end..].trim_start().strip_prefix('=') else {
+        return Ok(None);
+    };
+    let Some(in_start) = find_in_keyword(value) else {
+        return Ok(None);
+    };
+    Ok(Some((
+        name,
+        &value[..in_start],
+        &value[in_start + "in".len()..],
+    )))
+}
+
+/// Desugars `let x = e in body` and `let x e; body` anywhere in a term into `x(body).(e)`.
+/// The body extends to the end of the enclosing parentheses.
+fn replace_local_lets(text: &str) -> Result<String, String> {
+    let ends = block_ends(text);
+    let mut string = String::new();
+    // the end of every open block and the values of the lets in it, which follow their
+    // bodies at the end of the block, the innermost let first
+    let mut blocks: Vec<(usize, Vec<String>)> = vec![(text.len(), Vec::new())];
+    let mut index = 0;
+    while let Some((end, values)) = blocks.last_mut() {
+        let end = *end;
+        if index == end {
+            for value in values.iter().rev() {
+                string += &format!(").({value})");
+            }
+            blocks.pop();
+            if !blocks.is_empty() {
+                string.push(')');
+            }
+            index += 1;
+            continue;
+        }
+        let rest = &text[index..end];
+        if is_keyword_at(text, index, "let") {
+            let (name, value, body) = match split_local_let(rest)? {
+                Some(parts) => parts,
+                None => {
+                    let after_let = rest["let".len()..].trim_start();
+                    let name_end = after_let
+                        .find(char::is_whitespace)
+                        .ok_or("Expect a value after the name of a let-definition")?;
+                    let end = find_statement_end(after_let)
+                        .ok_or("Expect semicolon after let-definition")?;
+                    (
+                        &after_let[..name_end],
+                        &after_let[name_end..end],
+                        &after_let[end + 1..],
+                    )
+                }
+            };
+            string += name.trim();
+            string.push('(');
+            values.push(replace_local_lets(value)?);
+            index = end - body.len();
+            continue;
+        }
+        let c = rest.chars().next().unwrap();
+        string.push(c);
+        if c == '(' {
+            let block_end = ends.get(&index).ok_or("Expect closing parenthesis")?;
+            blocks.push((*block_end, Vec::new()));
+            index += 1;
+        } else {
+            index += c.len_utf8();
+        }
+    }
+    Ok(string)
+}
+
+/// Type checks the annotated lets, which are the outermost applied definitions of `compiled`.
+fn check_let_annotations(compiled: &str, lets: Vec<Let>) -> Result<(), String> {
+    let (lambda, bindings) = parse_program(compiled)?;
+    let mut annotations = HashMap::new();
+    let mut current = &lambda;
+    for definition in lets {
+        let Lambda::Definition {
+            name_index, body, ..
+        } = current
+        else {
+            unreachable!()
+        };
+        if let Some(annotation) = definition.annotation {
+            annotations.insert(*name_index, annotation);
+        }
+        current = body;
+    }
+    check_annotations(&lambda, &bindings, &annotations)
+}
+
+/// Compiles the program `text`, ignoring its assertions.
+#[cfg(test)]
+pub(crate) fn compile(text: &str) -> Result<String, String> {
+    compile_with_assertions(text).map(|(compiled, _)| compiled)
+}
 
-        let body = &text[args_end + 1..body_end];
-        for &arg in args {
-            string += arg;
-            string += "(";
+/// Compiles the program `text` and its assertions.
+pub(crate) fn compile_with_assertions(text: &str) -> Result<(String, Vec<Assertion>), String> {
+    let (compiled, assertions) = compile_with_spans(text)?;
+    Ok((strip_marks(&compiled), assertions))
+}
+
+/// Like `compile_with_assertions`, but the binders written in `text` keep a mark with their
+/// offset in `text`, see `parse_program_with_spans`.
+pub(crate) fn compile_with_spans(text: &str) -> Result<(String, Vec<Assertion>), String> {
+    compile_marked(&mark_binders(text), text).map_err(|error| strip_marks(&error))
+}
+
+fn compile_marked(marked: &str, text: &str) -> Result<(String, Vec<Assertion>), String> {
+    // literals first, as they may contain whitespace, `;` and parentheses
+    let (after_lets_replaced, lets, assertions) = replace_lets(&replace_literals(marked)?)?;
+    let compiled = lower(text, &after_lets_replaced)?;
+    if lets
+        .iter()
+        .any(|definition| definition.annotation.is_some())
+    {
+        check_let_annotations(&compiled, lets)?;
+    }
+    let assertions = assertions
+        .into_iter()
+        .map(|assertion| {
+            Ok(Assertion {
+                left: strip_marks(&lower(text, &assertion.left)?),
+                right: strip_marks(&lower(text, &assertion.right)?),
+                ..assertion
+            })
+        })
+        .collect::<Result<_, String>>()?;
+    Ok((compiled, assertions))
+}
+
+/// Lowers a program without let statements to the syntax of the parser.
+fn lower(text: &str, after_lets_replaced: &str) -> Result<String, String> {
+    let after_local_lets_replaced = replace_local_lets(after_lets_replaced)?;
+    let without_whitespace = remove_whitespace(&after_local_lets_replaced);
+    if without_whitespace.contains(':') {
+        return Err(
+            "type annotations are only supported on let definitions and their parameters"
+                .to_string(),
+        );
+    }
+    let compiled = replace_comma_definition(&without_whitespace)?;
+    assert!(!compiled.contains('(') || find_block_end(&compiled).is_some());
+    let mut free = Vec::new();
+    collect_free_names(&compiled, &mut free);
+    if let Some((name, _)) = free.iter().find(|(_, is_call)| *is_call) {
+        return Err(format!(
+            "undefined name `{name}` at line {}",
+            line_of(text, name)
+        ));
+    }
+    Ok(compiled)
+}
+
+/// Replaces `a,b(body)` by `a(b(body))`.
+fn replace_comma_definition(text: &str) -> Result<String, String> {
+    let regex = Regex::new("[^(),.]+(,[^(),.]+)+").unwrap();
+    let ends = block_ends(text);
+    let mut definitions = Vec::new();
+    // the number of parentheses to add after the end of every body
+    let mut closing = HashMap::new();
+    for found in regex.find_iter(text) {
+        let body_end = ends
+            .get(&found.end())
+            .ok_or_else(|| format!("Expect a body after the parameters `{}`", found.as_str()))?;
+        *closing.entry(*body_end).or_insert(0) += found.as_str().matches(',').count();
+        definitions.push(found);
+    }
+    let mut string = String::new();
+    let mut definitions = definitions.into_iter().peekable();
+    let mut index = 0;
+    while index < text.len() {
+        if let Some(definition) = definitions.next_if(|found| found.start() == index) {
+            string += &definition.as_str().replace(',', "(");
+            index = definition.end();
+            continue;
         }
-        string += body;
-        for _ in 0..number_of_args {
-            string += ")";
+        let c = text[index..].chars().next().unwrap();
+        string.push(c);
+        if let Some(count) = closing.get(&index) {
+            string += &")".repeat(*count);
         }
-        string += rest;
-        text = string;
+        index += c.len_utf8();
     }
+    Ok(string)
 }
 
 #[cfg(test)]
@@ -74,7 +884,7 @@ mod tests {
     #[test]
     fn no_let() {
         let text = "a(a.5).a(a)";
-        let compiled = compile(text);
+        let compiled = compile(text).unwrap();
         assert_eq!(compiled, text);
     }
 
@@ -82,7 +892,7 @@ mod tests {
     fn single_let() {
         let text = "let f a(a.5);
         f.a(a)";
-        let compiled = remove_whitespace(&compile(text));
+        let compiled = remove_whitespace(&compile(text).unwrap());
         assert_eq!(compiled, "f(f.a(a)).(a(a.5))")
     }
 
@@ -92,7 +902,7 @@ mod tests {
         let f a(a.5);
         let g a(a.3);
         f.g";
-        let compiled = remove_whitespace(&compile(text));
+        let compiled = remove_whitespace(&compile(text).unwrap());
         assert_eq!(compiled, "f(g(f.g).(a(a.3))).(a(a.5))")
     }
 
@@ -101,7 +911,7 @@ mod tests {
         let text = "
             a,b(b).5.3
         ";
-        let compiled = remove_whitespace(&compile(text));
+        let compiled = remove_whitespace(&compile(text).unwrap());
         assert_eq!(compiled, "a(b(b)).5.3");
     }
 
@@ -110,7 +920,245 @@ mod tests {
         let text = "
             w(a,b(c,d(d).7).5.3)
         ";
-        let compiled = remove_whitespace(&compile(text));
+        let compiled = remove_whitespace(&compile(text).unwrap());
         assert_eq!(compiled, "w(a(b(c(d(d)).7)).5.3)");
     }
+
+    #[test]
+    fn let_without_annotation_is_unchanged() {
+        let text = "let id x(x); id";
+        assert_eq!(compile(text), Ok("id(id).(x(x))".to_string()));
+    }
+
+    #[test]
+    fn annotations_are_stripped() {
+        let annotated = "
+        type Nat = (a -> a) -> a -> a;
+        let zero : Nat = f,x(x);
+        let succ n:Nat,f,x(
+            f.(n.f.x)
+        );
+        let add : Nat -> Nat -> Nat = m,n(
+            f,x((m.f).(n.f.x))
+        );
+        add.(succ.zero).zero
+        ";
+        let plain = "
+        let zero f,x(x);
+        let succ n,f,x(
+            f.(n.f.x)
+        );
+        let add m,n(
+            f,x((m.f).(n.f.x))
+        );
+        add.(succ.zero).zero
+        ";
+        assert_eq!(compile(annotated), compile(plain));
+        assert!(compile(plain).is_ok());
+    }
+
+    #[test]
+    fn annotation_of_single_parameter() {
+        let text = "let id x : a (x); id";
+        assert_eq!(compile(text), Ok("id(id).(x(x))".to_string()));
+    }
+
+    #[test]
+    fn annotation_mismatch() {
+        let text = "
+        let two : a -> a = f,x(f.(f.x));
+        two
+        ";
+        assert_eq!(
+            compile(text),
+            Err("definition `two` has type `(a -> a) -> a -> a`, which does not match its annotation `a -> a`".to_string())
+        );
+    }
+
+    #[test]
+    fn annotation_is_too_general() {
+        let text = "let id : a -> b = x(x); id";
+        assert!(compile(text)
+            .unwrap_err()
+            .starts_with("definition `id` has type `a -> a`"));
+    }
+
+    #[test]
+    fn parameter_annotation_mismatch() {
+        let text = "let apply x:a,y:b(x.y); apply";
+        assert_eq!(
+            compile(text),
+            Err("definition `apply` has type `(a -> b) -> a -> b`, which does not match its annotation `a -> b -> _`".to_string())
+        );
+    }
+
+    #[test]
+    fn annotations_restrict_later_uses() {
+        let text = "
+        let id : (a -> a) -> a -> a = x(x);
+        let k x,y(x);
+        let apply : a = id.k;
+        apply
+        ";
+        assert!(compile(text)
+            .unwrap_err()
+            .starts_with("type error in `id.(k)`"));
+    }
+
+    #[test]
+    fn unknown_type() {
+        let text = "let id : Foo = x(x); id";
+        assert_eq!(compile(text), Err("unknown type `Foo`".to_string()));
+    }
+
+    #[test]
+    fn only_annotated_values_are_checked() {
+        // the body and the other definitions have no simple type
+        let text = "
+        let id : a -> a = x(x);
+        let omega x(x.x);
+        let fix f(x(f.(x.x)).(x(f.(x.x))));
+        let const : a -> b -> a = x,_y(fix.omega.x);
+        id.(y(y))
+        ";
+        assert!(compile(text).is_ok());
+        assert!(compile("let omega x(x.x); let two : a = omega; two").is_ok());
+        assert!(compile("let id x(x); let two : a -> a = id.id; two").is_ok());
+        assert!(compile("let two : a -> a -> a = x(x); two").is_err());
+    }
+
+    #[test]
+    fn annotation_outside_of_let() {
+        assert!(compile("f(x:a(x))").is_err());
+    }
+
+    #[test]
+    fn local_let_in() {
+        let text = "f(let y = f.f in y.y)";
+        assert_eq!(compile(text), Ok("f(y(y.y).(f.f))".to_string()));
+    }
+
+    #[test]
+    fn local_let_statement_in_argument() {
+        let text = "
+        g(g.(
+            let x a(a);
+            x.x
+        ))";
+        assert_eq!(compile(text), Ok("g(g.(x(x.x).(a(a))))".to_string()));
+    }
+
+    #[test]
+    fn nested_local_lets() {
+        let text = "let x = let y = a(a) in y in x";
+        assert_eq!(compile(text), Ok("x(x).(y(y).(a(a)))".to_string()));
+    }
+
+    #[test]
+    fn local_let_in_let_body() {
+        let text = "
+        let id x(let y = x in y);
+        let twice f(x(let g f; g.(g.x)));
+        twice.id";
+        assert_eq!(
+            compile(text),
+            Ok("id(twice(twice.id).(f(x(g(g.(g.x)).(f))))).(x(y(y).(x)))".to_string())
+        );
+    }
+
+    #[test]
+    fn local_let_in_value_of_let() {
+        let text = "let x = (let y = a(a) in y) in x";
+        assert_eq!(compile(text), Ok("x(x).((y(y).(a(a))))".to_string()));
+    }
+
+    #[test]
+    fn letrec() {
+        let text = "
+        letrec loop x(loop.x);
+        loop.y";
+        assert_eq!(
+            compile(text),
+            Ok("loop(loop.y).(f(x'(f.(x'.x')).(x'(f.(x'.x')))).(loop(x(loop.x))))".to_string())
+        );
+    }
+
+    #[test]
+    fn letrec_group() {
+        let text = "letrec a b.a and b a.b; a";
+        assert_eq!(
+            compile(text),
+            Ok(concat!(
+                "rec(a(b(a).(rec.(x'(x_1(x_1))))).(rec.(x'(x_1(x'))))).",
+                "(f(x(f.(x.x)).(x(f.(x.x)))).(rec(select(select",
+                ".(a(b(b.a).(rec.(x'(x_1(x_1))))).(rec.(x'(x_1(x')))))",
+                ".(a(b(a.b).(rec.(x'(x_1(x_1))))).(rec.(x'(x_1(x')))))))))"
+            )
+            .to_string())
+        );
+    }
+
+    #[test]
+    fn letrec_with_annotation() {
+        let text = "letrec f : a -> a = x(f.x); f";
+        assert!(compile(text).is_err());
+    }
+
+    #[test]
+    fn lets_are_ordered_by_dependencies() {
+        let text = "
+        let two succ.one;
+        let one succ.zero;
+        let succ n,f,x(f.(n.f.x));
+        let zero f,x(x);
+        two";
+        let expected = compile(
+            "
+        let succ n,f,x(f.(n.f.x));
+        let zero f,x(x);
+        let one succ.zero;
+        let two succ.one;
+        two",
+        );
+        assert_eq!(compile(text), expected);
+    }
+
+    #[test]
+    fn parameters_are_not_dependencies() {
+        // `n` is a parameter of `succ`, not the later definition
+        let text = "
+        let succ n,f,x(f.(n.f.x));
+        let n succ.zero;
+        let zero f,x(x);
+        n";
+        assert_eq!(
+            compile(text),
+            Ok("succ(zero(n(n).(succ.zero)).(f(x(x)))).(n(f(x(f.(n.f.x)))))".to_string())
+        );
+    }
+
+    #[test]
+    fn undefined_name() {
+        let text = "
+        let zero f,x(x);
+        succ.zero";
+        assert_eq!(
+            compile(text),
+            Err("undefined name `succ` at line 3".to_string())
+        );
+    }
+
+    #[test]
+    fn cyclic_definitions() {
+        let text = "
+        let zero f,x(x);
+        let a b.zero;
+        let b c.zero;
+        let c b.zero;
+        a";
+        assert_eq!(
+            compile(text),
+            Err("cyclic definitions `b` -> `c` -> `b`, use letrec for recursion".to_string())
+        );
+    }
 }
@@ -0,0 +1,277 @@
+//! `blis check-confluence`: reduces a program with several strategies and checks that all
+//! of them that terminate reach the same normal form.
+
+use std::fs;
+
+use crate::{
+    arena::{Arena, TermId},
+    compiler::compile_with_assertions,
+    generate::Rng,
+    helpers::{alpha_equivalent, format_lambda},
+    parser::parse_program,
+    reducer::reduce_nth,
+    Lambda, ITERATIONS,
+};
+
+const RANDOM_PATHS: u64 = 8;
+/// Strategies that only reduce arguments which are dropped later can let the term grow
+/// without bound, such paths are given up when the term gets this large. Normal order is
+/// not bounded, it finds every normal form that exists.
+const MAX_SIZE: usize = 2000;
+
+pub(crate) enum Verdict<'a> {
+    Confluent,
+    /// two paths with different normal forms
+    NotConfluent(&'a Path, &'a Path),
+    /// fewer than two paths terminated, so there was nothing to compare
+    Inconclusive,
+}
+
+/// The terms a strategy went through, ending with the normal form if it terminated.
+/// The strategies reduce in an arena shared by all of them, as consecutive terms differ
+/// only in a small part.
+pub(crate) struct Path {
+    pub(crate) strategy: String,
+    trace: Vec<TermId>,
+    bindings: Vec<String>,
+    pub(crate) terminated: bool,
+}
+
+impl Path {
+    fn normal_form(&self) -> Option<TermId> {
+        self.terminated.then(|| *self.trace.last().unwrap())
+    }
+
+    fn format_trace(&self, arena: &Arena) -> String {
+        self.trace
+            .iter()
+            .enumerate()
+            .map(|(step, term)| {
+                let term = arena.to_lambda(*term);
+                format!("  {step}: {}\n", format_lambda(&term, &self.bindings))
+            })
+            .collect()
+    }
+}
+
+/// Follows the strategy `choose`, which picks the redex to contract from the number of
+/// redexes, for at most `ITERATIONS` steps and while the term is not larger than `max_size`.
+fn follow(
+    strategy: String,
+    lambda: &Lambda,
+    bindings: &[String],
+    arena: &mut Arena,
+    max_size: usize,
+    mut choose: impl FnMut(usize) -> usize,
+) -> Path {
+    let mut bindings = bindings.to_vec();
+    let mut current = arena.insert(lambda);
+    let mut trace = vec![current];
+    for _ in 0..ITERATIONS {
+        let count = arena.redexes(current);
+        if count == 0 {
+            return Path {
+                strategy,
+                trace,
+                bindings,
+                terminated: true,
+            };
+        }
+        if arena.size(current) > max_size {
+            break;
+        }
+        current = reduce_nth(arena, current, &mut choose(count), &mut bindings).unwrap();
+        trace.push(current);
+    }
+    Path {
+        strategy,
+        trace,
+        bindings,
+        terminated: false,
+    }
+}
+
+/// Reduces `lambda` leftmost outermost, rightmost innermost and with random redex choices.
+pub(crate) fn explore(lambda: &Lambda, bindings: &[String], arena: &mut Arena) -> Vec<Path> {
+    let mut paths = vec![
+        follow(
+            "normal order".to_owned(),
+            lambda,
+            bindings,
+            arena,
+            usize::MAX,
+            |_| 0,
+        ),
+        // the last redex contains no other redex
+        follow(
+            "innermost".to_owned(),
+            lambda,
+            bindings,
+            arena,
+            MAX_SIZE,
+            |count| count - 1,
+        ),
+    ];
+    for seed in 1..=RANDOM_PATHS {
+        let mut rng = Rng::new(seed);
+        let strategy = format!("random (seed {seed})");
+        paths.push(follow(
+            strategy,
+            lambda,
+            bindings,
+            arena,
+            MAX_SIZE,
+            |count| rng.below(count),
+        ));
+    }
+    paths
+}
+
+/// Finds two terminated paths with normal forms that are not alpha-equivalent.
+pub(crate) fn find_divergence<'a>(
+    paths: &'a [Path],
+    arena: &Arena,
+) -> Option<(&'a Path, &'a Path)> {
+    let terminated: Vec<&Path> = paths.iter().filter(|path| path.terminated).collect();
+    let first = terminated.first()?;
+    let first_normal_form = first.normal_form().unwrap();
+    let first_lambda = arena.to_lambda(first_normal_form);
+    terminated
+        .iter()
+        .find(|path| {
+            // equal ids are equal terms, which only leaves renamed binders to compare
+            let normal_form = path.normal_form().unwrap();
+            normal_form != first_normal_form
+                && !alpha_equivalent(
+                    &first_lambda,
+                    &first.bindings,
+                    &arena.to_lambda(normal_form),
+                    &path.bindings,
+                )
+        })
+        .map(|path| (*first, *path))
+}
+
+pub(crate) fn verdict<'a>(paths: &'a [Path], arena: &Arena) -> Verdict<'a> {
+    if paths.iter().filter(|path| path.terminated).count() < 2 {
+        return Verdict::Inconclusive;
+    }
+    match find_divergence(paths, arena) {
+        Some((a, b)) => Verdict::NotConfluent(a, b),
+        None => Verdict::Confluent,
+    }
+}
+
+/// Checks the confluence of the program in `file`, printing the steps of every strategy.
+/// Returns the exit status: 0 if the normal forms agree, 1 if they do not or the file is
+/// invalid and 2 if fewer than two strategies reached a normal form.
+pub(crate) fn check_confluence(file: &str) -> i32 {
+    let compiled = fs::read_to_string(file)
+        .map_err(|error| format!("Error reading file: {error}"))
+        .and_then(|contents| compile_with_assertions(&contents))
+        .and_then(|(compiled, _)| parse_program(&compiled));
+    let (lambda, bindings) = match compiled {
+        Ok(program) => program,
+        Err(error) => {
+            println!("{error}");
+            return 1;
+        }
+    };
+    let mut arena = Arena::default();
+    let paths = explore(&lambda, &bindings, &mut arena);
+    for path in &paths {
+        let steps = path.trace.len() - 1;
+        if path.terminated {
+            println!("{}: normal form after {steps} steps", path.strategy);
+        } else {
+            println!("{}: no normal form after {steps} steps", path.strategy);
+        }
+    }
+    let terminated = paths.iter().filter(|path| path.terminated).count();
+    match verdict(&paths, &arena) {
+        Verdict::Confluent => {
+            println!(
+                "confluent: {terminated} of {} strategies reached the same normal form",
+                paths.len()
+            );
+            0
+        }
+        Verdict::Inconclusive => {
+            println!(
+                "inconclusive: {terminated} of {} strategies reached a normal form",
+                paths.len()
+            );
+            2
+        }
+        Verdict::NotConfluent(a, b) => {
+            println!(
+                "not confluent: {} and {} reached different normal forms",
+                a.strategy, b.strategy
+            );
+            println!("{}:\n{}", a.strategy, a.format_trace(&arena));
+            println!("{}:\n{}", b.strategy, b.format_trace(&arena));
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{arena::Arena, compiler::compile, parser::parse_program};
+
+    use super::{explore, find_divergence, verdict, Verdict, MAX_SIZE};
+
+    #[test]
+    fn agreeing_strategies() {
+        let (lambda, bindings) =
+            parse_program(&compile("let succ n,f,x(f.(n.f.x)); succ.(succ.(_f,x(x)))").unwrap())
+                .unwrap();
+        let mut arena = Arena::default();
+        let paths = explore(&lambda, &bindings, &mut arena);
+        assert!(paths.iter().all(|path| path.terminated));
+        assert!(matches!(verdict(&paths, &arena), Verdict::Confluent));
+    }
+
+    #[test]
+    fn paths_without_normal_form_are_ignored() {
+        // the innermost strategy keeps reducing the argument that is dropped
+        let (lambda, bindings) =
+            parse_program(&compile("_x(y).(x(x.x).(x(x.x)))").unwrap()).unwrap();
+        let mut arena = Arena::default();
+        let paths = explore(&lambda, &bindings, &mut arena);
+        assert!(paths[0].terminated);
+        assert!(!paths[1].terminated);
+        assert!(find_divergence(&paths, &arena).is_none());
+    }
+
+    #[test]
+    fn fewer_than_two_normal_forms_are_inconclusive() {
+        let (lambda, bindings) = parse_program("x(x.x).(x(x.x))").unwrap();
+        let mut arena = Arena::default();
+        let paths = explore(&lambda, &bindings, &mut arena);
+        assert!(paths.iter().all(|path| !path.terminated));
+        assert!(matches!(verdict(&paths, &arena), Verdict::Inconclusive));
+    }
+
+    #[test]
+    fn normal_order_is_not_bounded_in_size() {
+        let numeral = format!("f(x({}x{}))", "f.(".repeat(MAX_SIZE), ")".repeat(MAX_SIZE));
+        let (lambda, bindings) = parse_program(&format!("y(y).({numeral})")).unwrap();
+        let mut arena = Arena::default();
+        let paths = explore(&lambda, &bindings, &mut arena);
+        assert!(paths[0].terminated);
+        assert!(paths[1..].iter().all(|path| !path.terminated));
+    }
+
+    #[test]
+    fn different_normal_forms_are_found() {
+        let (a, a_bindings) = parse_program("x(x)").unwrap();
+        let (b, b_bindings) = parse_program("x(y(x))").unwrap();
+        let mut arena = Arena::default();
+        let mut paths = explore(&a, &a_bindings, &mut arena);
+        paths.extend(explore(&b, &b_bindings, &mut arena));
+        let (first, second) = find_divergence(&paths, &arena).unwrap();
+        assert!(first.format_trace(&arena).contains("0: x(x)"));
+        assert!(second.format_trace(&arena).contains("0: x(y(x))"));
+    }
+}
@@ -0,0 +1,273 @@
+//! Scott encoded data types: `data List = Nil | Cons head tail;` declares constructors,
+//! `match xs { Nil => e1; Cons h t => e2 }` selects the branch of the constructor.
+//!
+//! A value is a function taking one case per constructor and applying the case of its
+//! constructor to the fields, so `Cons.h.t` is `on_Nil,on_Cons(on_Cons.h.t)` and the match
+//! is `xs.(e1).(h,t(e2))`.
+
+use crate::helpers::fresh_name;
+
+#[derive(Debug, PartialEq)]
+struct Constructor {
+    name: String,
+    fields: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct DataType {
+    name: String,
+    constructors: Vec<Constructor>,
+}
+
+/// Parses `List = Nil | Cons head tail` following a `data`.
+pub(crate) fn parse_data(statement: &str) -> Result<DataType, String> {
+    let (name, constructors) = statement
+        .split_once('=')
+        .ok_or("expected `=` in data declaration")?;
+    let name = name.trim().to_owned();
+    let constructors = constructors
+        .split('|')
+        .map(|constructor| {
+            let mut words = constructor.split_whitespace().map(str::to_owned);
+            let name = words
+                .next()
+                .ok_or_else(|| format!("empty constructor in the declaration of `{name}`"))?;
+            Ok(Constructor {
+                name,
+                fields: words.collect(),
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(DataType { name, constructors })
+}
+
+/// Returns the name and the body of a let for every constructor of `data`.
+/// The names of the cases are chosen so that `is_taken` is false for them.
+pub(crate) fn constructor_lets(
+    data: &DataType,
+    is_taken: impl Fn(&str) -> bool,
+) -> Vec<(String, String)> {
+    let cases: Vec<String> = data
+        .constructors
+        .iter()
+        .map(|constructor| fresh_name(&format!("on_{}", constructor.name), &is_taken))
+        .collect();
+    data.constructors
+        .iter()
+        .zip(&cases)
+        .map(|(constructor, case)| {
+            let parameters: Vec<&str> = constructor
+                .fields
+                .iter()
+                .map(String::as_str)
+                .chain(cases.iter().map(String::as_str))
+                .collect();
+            let body = if constructor.fields.is_empty() {
+                case.clone()
+            } else {
+                format!("{case}.{}", constructor.fields.join("."))
+            };
+            (
+                constructor.name.clone(),
+                format!("{}({body})", parameters.join(",")),
+            )
+        })
+        .collect()
+}
+
+/// Finds the end of the block starting with `open` at the start of `text`.
+fn find_end(text: &str, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+    for (index, c) in text.char_indices() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                return Some(index);
+            }
+        }
+    }
+    None
+}
+
+fn is_match_at(text: &str, index: usize) -> bool {
+    if !text[index..].starts_with("match") {
+        return false;
+    }
+    let before = text[..index].chars().next_back();
+    let after = text[index + "match".len()..].chars().next();
+    before.is_none_or(|c| c.is_whitespace() || c == '(')
+        && after.is_some_and(|c| c.is_whitespace() || c == '(')
+}
+
+/// Splits the branches of a match at the `;`s outside of parentheses and braces.
+fn split_branches(text: &str) -> Vec<&str> {
+    let mut branches = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (index, c) in text.char_indices() {
+        match c {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth -= 1,
+            ';' if depth == 0 => {
+                branches.push(&text[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    branches.push(&text[start..]);
+    branches
+        .into_iter()
+        .filter(|branch| !branch.trim().is_empty())
+        .collect()
+}
+
+fn desugar_match(scrutinee: &str, branches: &str, data: &[DataType]) -> Result<String, String> {
+    let mut cases = Vec::new();
+    let mut wildcard = None;
+    for branch in split_branches(branches) {
+        let (pattern, expression) = branch
+            .split_once("=>")
+            .ok_or("expected `=>` after the pattern of a match branch")?;
+        let expression = replace_matches(expression.trim(), data)?;
+        let mut words = pattern.split_whitespace();
+        let constructor = words.next().ok_or("empty pattern in match")?;
+        if constructor == "_" {
+            wildcard = Some(expression);
+            continue;
+        }
+        let fields: Vec<&str> = words.collect();
+        cases.push((constructor, fields, expression));
+    }
+    let (first, ..) = cases
+        .first()
+        .ok_or("a match needs at least one constructor")?;
+    let data_type = data
+        .iter()
+        .find(|data_type| data_type.constructors.iter().any(|c| c.name == *first))
+        .ok_or_else(|| format!("unknown constructor `{first}`"))?;
+    let mut arguments = String::new();
+    for constructor in &data_type.constructors {
+        let mut matching = cases.iter().filter(|(name, ..)| *name == constructor.name);
+        let case = match (matching.next(), matching.next()) {
+            (Some(_), Some(_)) => {
+                return Err(format!("`{}` is matched more than once", constructor.name));
+            }
+            (Some((_, fields, expression)), None) => {
+                if fields.len() != constructor.fields.len() {
+                    return Err(format!(
+                        "`{}` has {} fields, but the pattern binds {}",
+                        constructor.name,
+                        constructor.fields.len(),
+                        fields.len()
+                    ));
+                }
+                (fields.clone(), expression)
+            }
+            (None, _) => {
+                let expression = wildcard
+                    .as_ref()
+                    .ok_or_else(|| format!("the match does not cover `{}`", constructor.name))?;
+                (vec!["_"; constructor.fields.len()], expression)
+            }
+        };
+        arguments += &match case {
+            (fields, expression) if fields.is_empty() => format!(".({expression})"),
+            (fields, expression) => format!(".({}({expression}))", fields.join(",")),
+        };
+    }
+    if let Some((name, ..)) = cases
+        .iter()
+        .find(|(name, ..)| !data_type.constructors.iter().any(|c| c.name == *name))
+    {
+        return Err(format!(
+            "`{name}` is not a constructor of `{}`",
+            data_type.name
+        ));
+    }
+    let scrutinee = replace_matches(scrutinee.trim(), data)?;
+    if scrutinee.contains(['(', '.']) {
+        Ok(format!("(({scrutinee}){arguments})"))
+    } else {
+        Ok(format!("({scrutinee}{arguments})"))
+    }
+}
+
+/// Replaces every `match` in `text` by the application of the matched value to the branches.
+pub(crate) fn replace_matches(text: &str, data: &[DataType]) -> Result<String, String> {
+    let mut string = String::new();
+    let mut index = 0;
+    while index < text.len() {
+        if is_match_at(text, index) {
+            let rest = &text[index + "match".len()..];
+            let open = rest
+                .find('{')
+                .ok_or("expected `{` after the value of a match")?;
+            let close = find_end(&rest[open..], '{', '}').ok_or("expected `}` after a match")?;
+            string += &desugar_match(&rest[..open], &rest[open + 1..open + close], data)?;
+            index += "match".len() + open + close + 1;
+            continue;
+        }
+        let c = text[index..].chars().next().unwrap();
+        string.push(c);
+        index += c.len_utf8();
+    }
+    Ok(string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{constructor_lets, parse_data, replace_matches};
+
+    fn list() -> Vec<super::DataType> {
+        vec![parse_data("List = Nil | Cons head tail").unwrap()]
+    }
+
+    #[test]
+    fn constructors() {
+        let lets = constructor_lets(&list()[0], |name| name == "on_Nil");
+        assert_eq!(
+            lets,
+            vec![
+                ("Nil".to_string(), "on_Nil',on_Cons(on_Nil')".to_string()),
+                (
+                    "Cons".to_string(),
+                    "head,tail,on_Nil',on_Cons(on_Cons.head.tail)".to_string()
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn match_in_constructor_order() {
+        assert_eq!(
+            replace_matches("match xs { Cons h t => h; Nil => zero }", &list()),
+            Ok("(xs.(zero).(h,t(h)))".to_string())
+        );
+        assert_eq!(
+            replace_matches("f(match tail.xs { Nil => a; _ => b; })", &list()),
+            Ok("f(((tail.xs).(a).(_,_(b))))".to_string())
+        );
+    }
+
+    #[test]
+    fn nested_match() {
+        let text = "match xs { Nil => a; Cons h t => match t { Nil => h; Cons x y => x } }";
+        assert_eq!(
+            replace_matches(text, &list()),
+            Ok("(xs.(a).(h,t((t.(h).(x,y(x))))))".to_string())
+        );
+    }
+
+    #[test]
+    fn invalid_matches() {
+        let data = list();
+        assert!(replace_matches("match xs { Nil => a }", &data).is_err());
+        assert!(replace_matches("match xs { Nil => a; Cons h => b }", &data).is_err());
+        assert!(replace_matches("match xs { Nil => a; Nil => a; Cons h t => b }", &data).is_err());
+        assert!(replace_matches("match xs { Leaf => a }", &data).is_err());
+        assert!(replace_matches("match xs { Nil => a", &data).is_err());
+    }
+}
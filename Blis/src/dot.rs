@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+
+use crate::{
+    arena::{Arena, TermId},
+    helpers::format_lambda,
+    Lambda,
+};
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+struct TreeWriter<'a> {
+    bindings: &'a [String],
+    lines: Vec<String>,
+    node_count: usize,
+    /// binder index and node of every definition the current node is nested in
+    scope: Vec<(usize, usize)>,
+}
+
+impl TreeWriter<'_> {
+    fn node(&mut self, label: &str) -> usize {
+        let node = self.node_count;
+        self.node_count += 1;
+        self.lines
+            .push(format!("    n{node} [label=\"{}\"];", escape(label)));
+        node
+    }
+
+    fn edge(&mut self, from: usize, to: usize, label: &str) {
+        self.lines
+            .push(format!("    n{from} -> n{to} [label=\"{label}\"];"));
+    }
+
+    fn back_edge(&mut self, from: usize, name_index: usize) {
+        let binder = self
+            .scope
+            .iter()
+            .rev()
+            .find(|(index, _)| *index == name_index);
+        if let Some((_, binder)) = binder {
+            self.lines.push(format!(
+                "    n{from} -> n{binder} [style=dashed, constraint=false];"
+            ));
+        }
+    }
+
+    /// Writes the nodes of `lambda` in preorder, each edge after the subtree it points to.
+    fn write(&mut self, lambda: &Lambda) {
+        enum Work<'l> {
+            Visit(&'l Lambda),
+            /// an edge to the node of the last visited subtree
+            Edge(usize, String),
+            /// the end of the body of a definition
+            Pop,
+        }
+        let mut work = vec![Work::Visit(lambda)];
+        let mut nodes = Vec::new();
+        while let Some(item) = work.pop() {
+            let lambda = match item {
+                Work::Visit(lambda) => lambda,
+                Work::Edge(from, label) => {
+                    let to = nodes.pop().unwrap();
+                    self.edge(from, to, &label);
+                    continue;
+                }
+                Work::Pop => {
+                    self.scope.pop();
+                    continue;
+                }
+            };
+            match lambda {
+                Lambda::Variable(value) => {
+                    let node = self.node(&self.bindings[*value].clone());
+                    self.back_edge(node, *value);
+                    nodes.push(node);
+                }
+                Lambda::Definition {
+                    name_index,
+                    body,
+                    parameter,
+                } => {
+                    let node = self.node(&format!("λ{}", self.bindings[*name_index]));
+                    nodes.push(node);
+                    self.scope.push((*name_index, node));
+                    if let Some(parameter) = parameter {
+                        work.push(Work::Edge(node, "parameter".to_owned()));
+                        work.push(Work::Visit(parameter));
+                    }
+                    work.push(Work::Edge(node, "body".to_owned()));
+                    work.push(Work::Pop);
+                    work.push(Work::Visit(body));
+                }
+                Lambda::Call {
+                    name_index,
+                    parameters,
+                } => {
+                    let node = self.node(&format!("{}.", self.bindings[*name_index]));
+                    nodes.push(node);
+                    self.back_edge(node, *name_index);
+                    for (position, parameter) in parameters.iter().enumerate().rev() {
+                        work.push(Work::Edge(node, (position + 1).to_string()));
+                        work.push(Work::Visit(parameter));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Renders the syntax tree of `lambda` in the Graphviz DOT language.
+/// Variables and calls point back to the definition binding them with a dashed edge.
+pub(crate) fn lambda_to_dot(lambda: &Lambda, bindings: &[String]) -> String {
+    let mut writer = TreeWriter {
+        bindings,
+        lines: Vec::new(),
+        node_count: 0,
+        scope: Vec::new(),
+    };
+    writer.write(lambda);
+    format!(
+        "digraph lambda {{\n    node [shape=box];\n{}\n}}\n",
+        writer.lines.join("\n")
+    )
+}
+
+/// Renders the terms visited during a reduction as a chain of nodes in the Graphviz DOT language.
+/// Terms that are visited more than once share a node, so cycles are visible.
+pub(crate) fn trace_to_dot(arena: &Arena, terms: &[TermId], bindings: &[String]) -> String {
+    let mut labels = HashMap::new();
+    let mut nodes = HashMap::new();
+    let mut lines = Vec::new();
+    let mut previous = None;
+    for (step, term) in terms.iter().enumerate() {
+        let label = labels
+            .entry(*term)
+            .or_insert_with(|| format_lambda(&arena.to_lambda(*term), bindings));
+        let node_count = nodes.len();
+        let node = *nodes.entry(label.clone()).or_insert_with(|| {
+            lines.push(format!("    t{node_count} [label=\"{}\"];", escape(label)));
+            node_count
+        });
+        if let Some(previous) = previous {
+            lines.push(format!("    t{previous} -> t{node} [label=\"{step}\"];"));
+        }
+        previous = Some(node);
+    }
+    format!(
+        "digraph reduction {{\n    node [shape=box];\n{}\n}}\n",
+        lines.join("\n")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{arena::Arena, parser::parse_program, reducer::full_reduce_visiting, Lambda};
+
+    use super::{lambda_to_dot, trace_to_dot};
+
+    #[test]
+    fn syntax_tree() {
+        let (lambda, bindings) = parse_program("a(a.b).c(c)").unwrap();
+        assert_eq!(
+            lambda_to_dot(&lambda, &bindings),
+            "digraph lambda {
+    node [shape=box];
+    n0 [label=\"λa\"];
+    n1 [label=\"a.\"];
+    n1 -> n0 [style=dashed, constraint=false];
+    n2 [label=\"b\"];
+    n1 -> n2 [label=\"1\"];
+    n0 -> n1 [label=\"body\"];
+    n3 [label=\"λc\"];
+    n4 [label=\"c\"];
+    n4 -> n3 [style=dashed, constraint=false];
+    n3 -> n4 [label=\"body\"];
+    n0 -> n3 [label=\"parameter\"];
+}
+"
+        );
+    }
+
+    #[test]
+    fn reduction_trace() {
+        let (lambda, mut bindings) = parse_program("a(a.b).c(c)").unwrap();
+        let mut arena = Arena::default();
+        let root = arena.insert(&lambda);
+        let mut terms = Vec::new();
+        let reduced =
+            full_reduce_visiting(&mut arena, root, &mut bindings, 10, |term| terms.push(term));
+        assert_eq!(arena.to_lambda(reduced), Lambda::var(2));
+        assert_eq!(
+            trace_to_dot(&arena, &terms, &bindings),
+            "digraph reduction {
+    node [shape=box];
+    t0 [label=\"a(a.(b)).(c(c))\"];
+    t1 [label=\"c(c).(b)\"];
+    t0 -> t1 [label=\"1\"];
+    t2 [label=\"b\"];
+    t1 -> t2 [label=\"2\"];
+}
+"
+        );
+    }
+
+    #[test]
+    fn repeated_terms_share_a_node() {
+        let bindings = vec!["x".to_string()];
+        let mut arena = Arena::default();
+        let terms = vec![arena.insert(&Lambda::var(0)), arena.insert(&Lambda::var(0))];
+        assert!(trace_to_dot(&arena, &terms, &bindings).contains("t0 -> t0"));
+    }
+}
@@ -0,0 +1,249 @@
+//! Running a program as a filter (`--io`): it is applied to stdin as a Church list of byte
+//! numerals, like a string literal, and has to return such a list, which is written to
+//! stdout one byte at a time, as soon as the byte is in normal form.
+//!
+//! The input is read lazily: its unread part is the free variable `_stdin`, which is
+//! replaced by the next cons cell only when the reduction cannot go on without it, so a
+//! program can answer a line before the next one is typed.
+
+use std::io::{BufRead, Bytes, Write};
+
+use crate::{
+    arena::{Arena, Node, TermId},
+    literals::decode_numeral,
+    reducer::{contract, find_reducible},
+    Lambda, ITERATIONS,
+};
+
+/// The head of `term`, the variable that is applied once the binders before it are removed.
+fn head(arena: &Arena, mut term: TermId) -> Option<usize> {
+    loop {
+        match arena.node(term) {
+            Node::Variable(value) => return Some(*value),
+            Node::Call { name_index, .. } => return Some(*name_index),
+            Node::Definition {
+                body,
+                parameter: None,
+                ..
+            } => term = *body,
+            Node::Definition { .. } => return None,
+        }
+    }
+}
+
+struct Filter<R> {
+    input: Bytes<R>,
+    /// the binders `_c`, `_n`, `_f` and `_x` of the input list and its numerals
+    names: [usize; 4],
+    /// the free variable standing for the unread input
+    rest: usize,
+    steps: usize,
+    arena: Arena,
+}
+
+impl<R: BufRead> Filter<R> {
+    /// Reads the next byte of the input as the list `_c,_n(_c.(byte).(_stdin._c._n))`, or
+    /// the empty list at its end.
+    fn read(&mut self) -> Result<TermId, String> {
+        let [c, n, f, x] = self.names;
+        let arena = &mut self.arena;
+        let tail = match self.input.next().transpose() {
+            Err(error) => return Err(format!("Error reading the input: {error}")),
+            Ok(None) => arena.var(n),
+            Ok(Some(byte)) => {
+                let mut numeral = arena.var(x);
+                for _ in 0..byte {
+                    numeral = arena.call(f, vec![numeral]);
+                }
+                let numeral = arena.def(x, numeral, None);
+                let numeral = arena.def(f, numeral, None);
+                let list = vec![arena.var(c), arena.var(n)];
+                let rest = arena.call(self.rest, list);
+                arena.call(c, vec![numeral, rest])
+            }
+        };
+        let list = arena.def(n, tail, None);
+        Ok(arena.def(c, list, None))
+    }
+
+    /// Contracts one redex of `term`, or substitutes the next part of the input if the
+    /// reduction depends on it. Returns whether `term` changed, it is in normal form if not.
+    fn step(&mut self, term: &mut TermId, bindings: &mut Vec<String>) -> Result<bool, String> {
+        self.steps += 1;
+        if self.steps > ITERATIONS {
+            return Err(format!(
+                "the output was not produced in {ITERATIONS} iterations"
+            ));
+        }
+        if head(&self.arena, *term) != Some(self.rest) {
+            match find_reducible(&mut self.arena, *term, bindings) {
+                Ok(reduced) => {
+                    *term = reduced;
+                    return Ok(true);
+                }
+                Err(_) if !self.arena.is_free(*term, self.rest) => return Ok(false),
+                Err(_) => {}
+            }
+        }
+        let next = self.read()?;
+        let redex = self.arena.def(self.rest, *term, Some(next));
+        *term = contract(&mut self.arena, redex, bindings);
+        Ok(true)
+    }
+}
+
+/// Applies `program` to `input` and writes the resulting bytes to `output`.
+pub(crate) fn run_filter(
+    program: Lambda,
+    bindings: &mut Vec<String>,
+    input: impl BufRead,
+    output: &mut impl Write,
+) -> Result<(), String> {
+    let first = bindings.len();
+    bindings.extend(["_c", "_n", "_f", "_x", "_stdin", "_program"].map(str::to_owned));
+    let mut filter = Filter {
+        input: input.bytes(),
+        names: [first, first + 1, first + 2, first + 3],
+        rest: first + 4,
+        steps: 0,
+        arena: Arena::default(),
+    };
+    let apply = first + 5;
+    let arena = &mut filter.arena;
+    let program = arena.insert(&program);
+    let rest = vec![arena.var(filter.rest)];
+    let body = arena.call(apply, rest);
+    let mut term = arena.def(apply, body, Some(program));
+    let not_a_list = || "the output is not a list of bytes".to_owned();
+    // reduce until the result is a list `c(n(body))`
+    let (c, n, mut body) = loop {
+        if let Node::Definition {
+            name_index: c,
+            body,
+            parameter: None,
+        } = filter.arena.node(term)
+        {
+            if let Node::Definition {
+                name_index: n,
+                body,
+                parameter: None,
+            } = filter.arena.node(*body)
+            {
+                break (*c, *n, *body);
+            }
+        }
+        if !filter.step(&mut term, bindings)? {
+            return Err(not_a_list());
+        }
+    };
+    // the arena keeps every step, so it is cleared of the terms that were written when it
+    // has doubled in size
+    let mut kept = filter.arena.len();
+    loop {
+        match filter.arena.node(body).clone() {
+            Node::Variable(value) if value == n => return Ok(()),
+            Node::Call {
+                name_index,
+                parameters,
+            } if name_index == c && parameters.len() == 2 => {
+                let mut head = parameters[0];
+                while filter.step(&mut head, bindings)? {}
+                let byte = decode_numeral(&filter.arena.to_lambda(head))
+                    .and_then(|value| u8::try_from(value).ok())
+                    .ok_or("the output contains an element that is not a byte")?;
+                output
+                    .write_all(&[byte])
+                    .and_then(|_| output.flush())
+                    .map_err(|error| format!("Error writing the output: {error}"))?;
+                body = parameters[1];
+                filter.steps = 0;
+                if filter.arena.len() > 2 * kept {
+                    body = filter.arena.retain(body);
+                    kept = filter.arena.len();
+                }
+            }
+            _ => {
+                if !filter.step(&mut body, bindings)? {
+                    return Err(not_a_list());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compiler::compile, parser::parse_program};
+
+    use std::io::{self, BufReader, Read};
+
+    use super::run_filter;
+
+    /// Gives out `bytes`, then fails instead of ending, like a terminal nobody types into.
+    struct Waiting<'a>(&'a [u8]);
+
+    impl Read for Waiting<'_> {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            match self.0.split_first() {
+                Some((byte, rest)) if !buffer.is_empty() => {
+                    buffer[0] = *byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Err(io::Error::new(io::ErrorKind::WouldBlock, "no input")),
+            }
+        }
+    }
+
+    fn run(program: &str, input: &[u8]) -> Result<Vec<u8>, String> {
+        let (lambda, mut bindings) = parse_program(&compile(program).unwrap()).unwrap();
+        let mut output = Vec::new();
+        run_filter(lambda, &mut bindings, input, &mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn echo() {
+        assert_eq!(run("input(input)", b"hello\n"), Ok(b"hello\n".to_vec()));
+        assert_eq!(run("input(input)", b""), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn transform() {
+        let reverse = "input(input.(h,t(c,n(t.c.(c.h.n)))).(_c,n(n)))";
+        assert_eq!(run(reverse, b"abc"), Ok(b"cba".to_vec()));
+        let greet = "let prepend xs,ys,c,n(xs.c.(ys.c.n)); prepend.\"hi \"";
+        assert_eq!(run(greet, b"you"), Ok(b"hi you".to_vec()));
+    }
+
+    #[test]
+    fn input_is_read_lazily() {
+        let (lambda, mut bindings) = parse_program(&compile("input(input)").unwrap()).unwrap();
+        let mut output = Vec::new();
+        assert!(run_filter(
+            lambda,
+            &mut bindings,
+            BufReader::new(Waiting(b"ab")),
+            &mut output
+        )
+        .is_err());
+        assert_eq!(output, b"ab");
+        let first = "input(input.(h,_t(c,n(c.h.n))).(_c,n(n)))";
+        let (lambda, mut bindings) = parse_program(&compile(first).unwrap()).unwrap();
+        let mut output = Vec::new();
+        run_filter(
+            lambda,
+            &mut bindings,
+            BufReader::new(Waiting(b"x")),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(output, b"x");
+    }
+
+    #[test]
+    fn invalid_output() {
+        assert!(run("_input(x(x))", b"").is_err());
+        assert!(run("_input(\"\\u{100}\")", b"").is_err());
+    }
+}
@@ -0,0 +1,264 @@
+//! Random well-scoped terms for property tests.
+//!
+//! Terms are bounded in depth and in size. Binders take their names from a given list of
+//! binding indices, which may repeat names to test shadowing, and the only free variables
+//! are the given ones, which are never called. Simply typed terms always have a normal form.
+
+use crate::{helpers::format_lambda, types::Type, Lambda};
+
+/// Small xorshift generator, so that generated terms are reproducible without dependencies.
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        // xorshift never leaves zero
+        Rng(seed.max(1))
+    }
+
+    pub(crate) fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
+
+pub(crate) struct Generator {
+    pub(crate) rng: Rng,
+    /// the bindings binders use, at least two different ones for simply typed terms
+    pub(crate) binders: Vec<usize>,
+    /// the bindings of the free variables, which are of the base type in typed terms
+    pub(crate) free: Vec<usize>,
+    pub(crate) max_depth: usize,
+    pub(crate) max_size: usize,
+    pub(crate) simply_typed: bool,
+}
+
+impl Generator {
+    /// A generator of closed, untyped terms of depth at most 6 and at most 40 nodes.
+    pub(crate) fn new(seed: u64, binders: Vec<usize>) -> Self {
+        Generator {
+            rng: Rng::new(seed),
+            binders,
+            free: Vec::new(),
+            max_depth: 6,
+            max_size: 40,
+            simply_typed: false,
+        }
+    }
+
+    pub(crate) fn term(&mut self) -> Lambda {
+        let mut size = self.max_size;
+        if !self.simply_typed {
+            return self.untyped(self.max_depth, &mut Vec::new(), &mut size);
+        }
+        let t = self.random_type(2);
+        if !self.free.is_empty() {
+            return self.typed(&t, self.max_depth, &mut Vec::new(), &mut size);
+        }
+        // closed terms start with a binder of the base type, so that it is always inhabited
+        let name_index = self.binders[self.rng.below(self.binders.len())];
+        let mut scope = vec![(name_index, Type::Variable(0))];
+        let body = self.typed(&t, self.max_depth - 1, &mut scope, &mut size);
+        Lambda::def(name_index, body, None)
+    }
+
+    fn untyped(&mut self, depth: usize, scope: &mut Vec<usize>, size: &mut usize) -> Lambda {
+        *size = size.saturating_sub(1);
+        let visible = scope.len() + self.free.len();
+        let choice = if depth == 0 || *size == 0 {
+            0
+        } else {
+            self.rng.below(4)
+        };
+        match choice {
+            0 if visible > 0 => Lambda::var(self.visible(scope)),
+            // free variables are not called, the compiler rejects calls of undefined names
+            3 if !scope.is_empty() => {
+                let name_index = scope[self.rng.below(scope.len())];
+                let parameters = (0..1 + self.rng.below(3))
+                    .map(|_| self.untyped(depth - 1, scope, size))
+                    .collect();
+                Lambda::call(name_index, parameters)
+            }
+            // without variables in scope, a leaf has to be a definition too
+            _ => {
+                let parameter = (choice != 0 && self.rng.below(2) == 0)
+                    .then(|| self.untyped(depth - 1, scope, size));
+                let name_index = self.binders[self.rng.below(self.binders.len())];
+                scope.push(name_index);
+                let body = self.untyped(depth.saturating_sub(1), scope, size);
+                scope.pop();
+                Lambda::def(name_index, body, parameter)
+            }
+        }
+    }
+
+    fn visible(&mut self, scope: &[usize]) -> usize {
+        let index = self.rng.below(scope.len() + self.free.len());
+        scope
+            .get(index)
+            .copied()
+            .unwrap_or_else(|| self.free[index - scope.len()])
+    }
+
+    fn random_type(&mut self, depth: usize) -> Type {
+        if depth == 0 || self.rng.below(2) == 0 {
+            return Type::Variable(0);
+        }
+        Type::function(self.random_type(depth - 1), self.random_type(depth - 1))
+    }
+
+    /// The visible variables with their types, the innermost binding of every name.
+    fn typed_scope(&self, scope: &[(usize, Type)]) -> Vec<(usize, Type)> {
+        let mut visible: Vec<(usize, Type)> = Vec::new();
+        for (name_index, t) in scope.iter().rev() {
+            if !visible.iter().any(|(visible, _)| visible == name_index) {
+                visible.push((*name_index, t.clone()));
+            }
+        }
+        for name_index in &self.free {
+            if !visible.iter().any(|(visible, _)| visible == name_index) {
+                visible.push((*name_index, Type::Variable(0)));
+            }
+        }
+        visible
+    }
+
+    /// Chooses a binder for a variable of type `t` that does not hide the last visible
+    /// variable of the base type.
+    fn typed_binder(&mut self, t: &Type, scope: &[(usize, Type)]) -> usize {
+        let visible = self.typed_scope(scope);
+        let base: Vec<usize> = visible
+            .iter()
+            .filter(|(_, t)| *t == Type::Variable(0))
+            .map(|(name_index, _)| *name_index)
+            .collect();
+        let candidates: Vec<usize> = self
+            .binders
+            .iter()
+            .copied()
+            .filter(|binder| *t == Type::Variable(0) || base != [*binder])
+            .collect();
+        candidates[self.rng.below(candidates.len())]
+    }
+
+    fn typed(
+        &mut self,
+        t: &Type,
+        depth: usize,
+        scope: &mut Vec<(usize, Type)>,
+        size: &mut usize,
+    ) -> Lambda {
+        *size = size.saturating_sub(1);
+        let leaf = depth == 0 || *size == 0;
+        let choice = if leaf { 0 } else { self.rng.below(4) };
+        if let (Type::Function(argument, result), 0 | 1) = (t, choice) {
+            let name_index = self.typed_binder(argument, scope);
+            scope.push((name_index, argument.as_ref().clone()));
+            let body = self.typed(result, depth.saturating_sub(1), scope, size);
+            scope.pop();
+            return Lambda::def(name_index, body, None);
+        }
+        if choice == 3 {
+            let argument = self.random_type(1);
+            let parameter = self.typed(&argument, depth - 1, scope, size);
+            let name_index = self.typed_binder(&argument, scope);
+            scope.push((name_index, argument));
+            let body = self.typed(t, depth - 1, scope, size);
+            scope.pop();
+            return Lambda::def(name_index, body, Some(parameter));
+        }
+        // a variable, applied to arguments until it has type `t`
+        let mut candidates = Vec::new();
+        for (name_index, variable_type) in self.typed_scope(scope) {
+            let mut arguments = Vec::new();
+            let mut current = &variable_type;
+            loop {
+                if current == t && (!leaf || arguments.is_empty()) {
+                    candidates.push((name_index, arguments.clone()));
+                }
+                let Type::Function(argument, result) = current else {
+                    break;
+                };
+                arguments.push(argument.as_ref().clone());
+                current = result;
+            }
+        }
+        if candidates.is_empty() {
+            // only functions lack a variable, they can always be built from a binder
+            let mut size = 0;
+            return self.typed(t, 0, scope, &mut size);
+        }
+        let (name_index, arguments) = candidates.swap_remove(self.rng.below(candidates.len()));
+        if arguments.is_empty() {
+            return Lambda::var(name_index);
+        }
+        let parameters = arguments
+            .iter()
+            .map(|argument| self.typed(argument, depth - 1, scope, size))
+            .collect();
+        Lambda::call(name_index, parameters)
+    }
+}
+
+/// Prints `count` random closed terms, one per line, for property tests outside of Blis.
+pub(crate) fn print_terms(seed: u64, count: usize, simply_typed: bool) {
+    let bindings = ["x", "y", "z", "f"].map(str::to_owned);
+    let mut generator = Generator::new(seed, (0..bindings.len()).collect());
+    generator.simply_typed = simply_typed;
+    for _ in 0..count {
+        println!("{}", format_lambda(&generator.term(), &bindings));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{helpers::collect_free, reducer::try_full_reduce, types::infer_type, Lambda};
+
+    use super::Generator;
+
+    fn depth(lambda: &Lambda) -> usize {
+        match lambda {
+            Lambda::Variable(_) => 0,
+            Lambda::Definition {
+                body, parameter, ..
+            } => 1 + depth(body).max(parameter.as_ref().map_or(0, |p| depth(p))),
+            Lambda::Call { parameters, .. } => 1 + parameters.iter().map(depth).max().unwrap_or(0),
+        }
+    }
+
+    fn names() -> Vec<String> {
+        ["x", "y", "x", "f", "c"].map(str::to_owned).to_vec()
+    }
+
+    #[test]
+    fn terms_are_well_scoped() {
+        let mut generator = Generator::new(7, vec![0, 1, 2, 3]);
+        generator.free = vec![4];
+        for _ in 0..1000 {
+            let lambda = generator.term();
+            let mut free = Vec::new();
+            collect_free(&lambda, &mut Vec::new(), &mut free);
+            assert!(free.iter().all(|index| *index == 4));
+            assert!(depth(&lambda) <= generator.max_depth + 1);
+        }
+    }
+
+    #[test]
+    fn simply_typed_terms_normalize() {
+        for free in [vec![], vec![4]] {
+            let mut generator = Generator::new(11, vec![0, 1, 2, 3]);
+            generator.free = free;
+            generator.simply_typed = true;
+            for _ in 0..300 {
+                let lambda = generator.term();
+                let mut free = Vec::new();
+                collect_free(&lambda, &mut Vec::new(), &mut free);
+                assert!(free.iter().all(|index| *index == 4));
+                assert!(infer_type(&lambda, &names()).is_ok());
+                assert!(try_full_reduce(lambda, &mut names(), 10000).is_some());
+            }
+        }
+    }
+}
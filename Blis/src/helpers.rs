@@ -1,4 +1,10 @@
-use crate::Lambda;
+use std::collections::HashMap;
+
+use crate::{
+    literals::decode_string,
+    pretty::{render, Doc},
+    Lambda,
+};
 
 pub(crate) fn find_block_end(text: &str) -> Option<usize> {
     let mut stack = 0;
@@ -17,86 +23,443 @@ pub(crate) fn find_block_end(text: &str) -> Option<usize> {
     None
 }
 
-pub(crate) fn format_lambda_indented(
-    lambda: &Lambda,
-    bindings: &Vec<String>,
-    depth: usize,
-    is_on_newline: bool,
-) -> String {
-    let whitespaces = " ".repeat(depth);
-    let mut string = String::new();
-    match lambda {
-        Lambda::Variable(value) => string += &bindings[*value],
-        Lambda::Definition {
-            name_index: input,
-            body,
-            parameter,
-        } => {
-            string += &format!(
-                "{}{}({}\n{})",
-                if is_on_newline {
-                    whitespaces.clone()
-                } else {
-                    "".to_string()
-                },
-                &bindings[*input],
-                whitespaces,
-                format_lambda_indented(body, bindings, depth + 1, true)
-            );
-
-            if let Some(value) = parameter {
-                string += &format!(
-                    "\n{whitespaces}.{}",
-                    format_lambda_indented(value, bindings, depth + 1, false)
-                );
-            };
+/// The position of the matching `)` of every `(` in `text` that is closed. Passes over
+/// deeply nested text look their blocks up here instead of searching for every block end.
+pub(crate) fn block_ends(text: &str) -> HashMap<usize, usize> {
+    let mut ends = HashMap::new();
+    let mut open = Vec::new();
+    for (index, c) in text.char_indices() {
+        if c == '(' {
+            open.push(index);
+        } else if c == ')' {
+            if let Some(start) = open.pop() {
+                ends.insert(start, index);
+            }
         }
-        Lambda::Call {
-            name_index: input,
-            parameters: args,
-        } => {
-            string += (if is_on_newline {
-                whitespaces.clone()
-            } else {
-                "".to_string()
-            } + &bindings[*input])
-                .as_str();
-            for arg in args {
-                string += &format!(
-                    "\n{}.({})",
-                    whitespaces.clone(),
-                    format_lambda_indented(arg, bindings, depth + 1, false)
-                );
+    }
+    ends
+}
+
+/// Assigns every binder a printable name that the parser resolves back to the same binder.
+/// Free variables keep their names (deduplicated among themselves) and are never reused by
+/// binders, because the parser keeps free names in scope for the rest of the program.
+struct Namer<'a> {
+    bindings: &'a [String],
+    free_names: HashMap<usize, String>,
+    scope: Vec<(usize, String)>,
+}
+
+impl<'a> Namer<'a> {
+    fn new(lambda: &Lambda, bindings: &'a [String]) -> Self {
+        let mut free = Vec::new();
+        collect_free(lambda, &mut Vec::new(), &mut free);
+        let mut free_names = HashMap::new();
+        let mut used = Vec::new();
+        for index in free {
+            let name = fresh_name(&bindings[index], |name| used.iter().any(|n| n == name));
+            used.push(name.clone());
+            free_names.insert(index, name);
+        }
+        Namer {
+            bindings,
+            free_names,
+            scope: Vec::new(),
+        }
+    }
+
+    fn is_taken(&self, name: &str) -> bool {
+        self.scope.iter().any(|(_, n)| n == name) || self.free_names.values().any(|n| n == name)
+    }
+
+    fn name_of(&self, index: usize) -> &str {
+        self.scope
+            .iter()
+            .rev()
+            .find(|(i, _)| *i == index)
+            .map(|(_, name)| name)
+            .unwrap_or_else(|| &self.free_names[&index])
+    }
+
+    fn push_binder(&mut self, index: usize) -> String {
+        let name = fresh_name(&self.bindings[index], |name| self.is_taken(name));
+        self.scope.push((index, name.clone()));
+        name
+    }
+
+    fn pop_binder(&mut self) {
+        self.scope.pop();
+    }
+
+    fn doc(&mut self, lambda: &Lambda) -> Doc {
+        enum Work<'l> {
+            Visit(&'l Lambda),
+            PopBinder,
+            Definition(String, bool),
+            Call(String, usize),
+        }
+        let mut work = vec![Work::Visit(lambda)];
+        let mut done: Vec<Doc> = Vec::new();
+        while let Some(item) = work.pop() {
+            match item {
+                Work::Visit(Lambda::Variable(value)) => done.push(Doc::text(self.name_of(*value))),
+                Work::Visit(Lambda::Definition {
+                    name_index: input,
+                    body,
+                    parameter,
+                }) => {
+                    // the parameter is outside of the scope of the binder
+                    let name = self.push_binder(*input);
+                    work.push(Work::Definition(name, parameter.is_some()));
+                    work.extend(parameter.as_deref().map(Work::Visit));
+                    work.push(Work::PopBinder);
+                    work.push(Work::Visit(body));
+                }
+                Work::Visit(Lambda::Call {
+                    name_index: input,
+                    parameters: args,
+                }) => {
+                    work.push(Work::Call(self.name_of(*input).to_owned(), args.len()));
+                    work.extend(args.iter().rev().map(Work::Visit));
+                }
+                Work::PopBinder => self.pop_binder(),
+                Work::Definition(name, has_parameter) => {
+                    let parameter = has_parameter.then(|| done.pop().unwrap());
+                    let body = done.pop().unwrap();
+                    let mut docs = vec![Doc::text(format!("{name}(")), indented(body)];
+                    docs.push(Doc::text(")"));
+
+                    if let Some(value) = parameter {
+                        docs.push(Doc::text(".("));
+                        docs.push(indented(value));
+                        docs.push(Doc::text(")"));
+                    };
+                    done.push(Doc::group(Doc::Concat(docs)));
+                }
+                Work::Call(name, count) => {
+                    let args = done.split_off(done.len() - count);
+                    let mut docs = vec![Doc::text(name)];
+                    for arg in args {
+                        docs.push(Doc::text(".("));
+                        docs.push(indented(arg));
+                        docs.push(Doc::text(")"));
+                    }
+                    done.push(Doc::group(Doc::Concat(docs)));
+                }
             }
         }
+        done.pop().unwrap()
+    }
+}
+
+/// Puts `doc` on its own, further indented line if the surrounding group is broken.
+fn indented(doc: Doc) -> Doc {
+    Doc::Concat(vec![
+        Doc::nest(2, Doc::Concat(vec![Doc::Break, doc])),
+        Doc::Break,
+    ])
+}
+
+pub(crate) fn collect_free(lambda: &Lambda, scope: &mut Vec<usize>, free: &mut Vec<usize>) {
+    enum Work<'l> {
+        Visit(&'l Lambda),
+        Body(usize, &'l Lambda),
+        PopScope,
+    }
+    let mut note = |index: usize, scope: &Vec<usize>| {
+        if !scope.contains(&index) && !free.contains(&index) {
+            free.push(index);
+        }
+    };
+    let mut work = vec![Work::Visit(lambda)];
+    while let Some(item) = work.pop() {
+        match item {
+            Work::Visit(Lambda::Variable(value)) => note(*value, scope),
+            Work::Visit(Lambda::Definition {
+                name_index,
+                body,
+                parameter,
+            }) => {
+                work.push(Work::Body(*name_index, body));
+                work.extend(parameter.as_deref().map(Work::Visit));
+            }
+            Work::Visit(Lambda::Call {
+                name_index,
+                parameters,
+            }) => {
+                note(*name_index, scope);
+                work.extend(parameters.iter().rev().map(Work::Visit));
+            }
+            Work::Body(name_index, body) => {
+                scope.push(name_index);
+                work.push(Work::PopScope);
+                work.push(Work::Visit(body));
+            }
+            Work::PopScope => {
+                scope.pop();
+            }
+        }
+    }
+}
+
+/// Returns `base` if it is free, otherwise the first free name of `base'`, `base_1`, `base_2`, ...
+pub(crate) fn fresh_name(base: &str, is_taken: impl Fn(&str) -> bool) -> String {
+    if !is_taken(base) {
+        return base.to_owned();
+    }
+    let primed = format!("{base}'");
+    if !is_taken(&primed) {
+        return primed;
+    }
+    (1..)
+        .map(|n| format!("{base}_{n}"))
+        .find(|name| !is_taken(name))
+        .unwrap()
+}
+
+/// Prints `lambda` as Blis source, so that parsing the output yields an alpha-equivalent term.
+/// Call heads have to be bound, as the parser does not accept free function names.
+pub(crate) fn format_lambda(lambda: &Lambda, bindings: &[String]) -> String {
+    format_lambda_pretty(lambda, bindings, usize::MAX)
+}
+
+/// Like `format_lambda`, but breaks definitions and arguments onto indented lines
+/// where the term does not fit into `width` columns.
+pub(crate) fn format_lambda_pretty(lambda: &Lambda, bindings: &[String], width: usize) -> String {
+    let doc = Namer::new(lambda, bindings).doc(lambda);
+    render(&doc, width)
+}
+
+/// Formats a normal form as it is printed by `--emit text`: strings as literals, other terms
+/// on one line if they fit into `width` columns.
+pub(crate) fn format_normal_form(lambda: &Lambda, bindings: &[String], width: usize) -> String {
+    if let Some(string) = decode_string(lambda) {
+        return format!("{string:?}");
+    }
+    let formatted = format_lambda(lambda, bindings);
+    if formatted.chars().count() <= width {
+        formatted
+    } else {
+        format_lambda_pretty(lambda, bindings, width)
     }
-    string
 }
 
-pub(crate) fn format_lambda(lambda: &Lambda, bindings: &Vec<String>) -> String {
-    let mut string = String::new();
-    match lambda {
-        Lambda::Variable(value) => string += &bindings[*value],
-        Lambda::Definition {
-            name_index: input,
-            body,
-            parameter,
-        } => {
-            string += &format!("{}({})", &bindings[*input], format_lambda(body, bindings));
-
-            if let Some(value) = parameter {
-                string += &format!(".{}", format_lambda(value, bindings));
-            };
+/// Compares two terms up to renaming of bound variables. Free variables are compared by name.
+pub(crate) fn alpha_equivalent(
+    a: &Lambda,
+    a_bindings: &[String],
+    b: &Lambda,
+    b_bindings: &[String],
+) -> bool {
+    fn resolve(index: usize, scope: &[usize]) -> Option<usize> {
+        scope.iter().rposition(|i| *i == index)
+    }
+    fn same_variable(
+        a: usize,
+        a_bindings: &[String],
+        a_scope: &[usize],
+        b: usize,
+        b_bindings: &[String],
+        b_scope: &[usize],
+    ) -> bool {
+        match (resolve(a, a_scope), resolve(b, b_scope)) {
+            (None, None) => a_bindings[a] == b_bindings[b],
+            (a_position, b_position) => a_position == b_position,
         }
-        Lambda::Call {
-            name_index: input,
-            parameters: args,
-        } => {
-            string += &bindings[*input];
-            for arg in args {
-                string += &format!(".({})", format_lambda(arg, bindings));
+    }
+    enum Work<'l> {
+        Compare(&'l Lambda, &'l Lambda),
+        Bodies(usize, &'l Lambda, usize, &'l Lambda),
+        PopScopes,
+    }
+    let (mut a_scope, mut b_scope) = (Vec::new(), Vec::new());
+    let mut work = vec![Work::Compare(a, b)];
+    while let Some(item) = work.pop() {
+        let (a, b) = match item {
+            Work::Compare(a, b) => (a, b),
+            Work::Bodies(a_name, a_body, b_name, b_body) => {
+                a_scope.push(a_name);
+                b_scope.push(b_name);
+                work.push(Work::PopScopes);
+                work.push(Work::Compare(a_body, b_body));
+                continue;
+            }
+            Work::PopScopes => {
+                a_scope.pop();
+                b_scope.pop();
+                continue;
+            }
+        };
+        match (a, b) {
+            (Lambda::Variable(a), Lambda::Variable(b))
+                if same_variable(*a, a_bindings, &a_scope, *b, b_bindings, &b_scope) => {}
+            (
+                Lambda::Definition {
+                    name_index: a_name,
+                    body: a_body,
+                    parameter: a_parameter,
+                },
+                Lambda::Definition {
+                    name_index: b_name,
+                    body: b_body,
+                    parameter: b_parameter,
+                },
+            ) => {
+                work.push(Work::Bodies(*a_name, a_body, *b_name, b_body));
+                match (a_parameter, b_parameter) {
+                    (None, None) => {}
+                    (Some(a), Some(b)) => work.push(Work::Compare(a, b)),
+                    _ => return false,
+                }
+            }
+            (
+                Lambda::Call {
+                    name_index: a_name,
+                    parameters: a_parameters,
+                },
+                Lambda::Call {
+                    name_index: b_name,
+                    parameters: b_parameters,
+                },
+            ) if same_variable(*a_name, a_bindings, &a_scope, *b_name, b_bindings, &b_scope)
+                && a_parameters.len() == b_parameters.len() =>
+            {
+                work.extend(
+                    a_parameters
+                        .iter()
+                        .zip(b_parameters)
+                        .map(|(a, b)| Work::Compare(a, b)),
+                );
             }
+            _ => return false,
         }
     }
-    string
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compiler::compile, generate::Generator, parser::parse_program, Lambda};
+
+    use super::{alpha_equivalent, format_lambda, format_lambda_pretty};
+
+    fn round_trip(lambda: &Lambda, bindings: &[String]) {
+        round_trip_text(&format_lambda(lambda, bindings), lambda, bindings);
+    }
+
+    fn round_trip_text(text: &str, lambda: &Lambda, bindings: &[String]) {
+        let (parsed, parsed_bindings) = parse_program(&compile(text).unwrap()).unwrap();
+        assert!(
+            alpha_equivalent(lambda, bindings, &parsed, &parsed_bindings),
+            "{text} was parsed as {}",
+            format_lambda(&parsed, &parsed_bindings)
+        );
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn definition_parameter_is_parenthesized() {
+        let bindings = names(&["a", "b", "c"]);
+        let lambda = Lambda::def(
+            0,
+            Lambda::var(0),
+            Some(Lambda::call(1, vec![Lambda::var(2)])),
+        );
+        let lambda = Lambda::def(1, lambda, None);
+        assert_eq!(format_lambda(&lambda, &bindings), "b(a(a).(b.(c)))");
+        round_trip(&lambda, &bindings);
+    }
+
+    #[test]
+    fn duplicated_names_are_disambiguated() {
+        // the bindings of the `naming_duplication` parser test
+        let bindings = names(&["a", "a", "5"]);
+        let lambda = Lambda::def(1, Lambda::def(0, Lambda::var(1), None), None);
+        assert_eq!(format_lambda(&lambda, &bindings), "a(a'(a))");
+        round_trip(&lambda, &bindings);
+    }
+
+    #[test]
+    fn shadowed_binder_is_renamed() {
+        let bindings = names(&["a", "a'"]);
+        let lambda = Lambda::def(
+            0,
+            Lambda::def(1, Lambda::def(0, Lambda::var(0), None), None),
+            None,
+        );
+        assert_eq!(format_lambda(&lambda, &bindings), "a(a'(a_1(a_1)))");
+        round_trip(&lambda, &bindings);
+    }
+
+    #[test]
+    fn binder_does_not_capture_free_variable() {
+        let bindings = names(&["x", "x"]);
+        let lambda = Lambda::def(0, Lambda::var(1), Some(Lambda::var(1)));
+        assert_eq!(format_lambda(&lambda, &bindings), "x'(x).(x)");
+        round_trip(&lambda, &bindings);
+    }
+
+    #[test]
+    fn alpha_equivalence() {
+        let bindings = names(&["a", "b", "c"]);
+        let a = Lambda::def(0, Lambda::var(0), Some(Lambda::var(2)));
+        let b = Lambda::def(1, Lambda::var(1), Some(Lambda::var(2)));
+        assert!(alpha_equivalent(&a, &bindings, &b, &bindings));
+        let c = Lambda::def(1, Lambda::var(2), Some(Lambda::var(2)));
+        assert!(!alpha_equivalent(&a, &bindings, &c, &bindings));
+    }
+
+    #[test]
+    fn pretty_printing_breaks_only_where_needed() {
+        let bindings = names(&["f", "x"]);
+        let numeral = Lambda::def(
+            0,
+            Lambda::def(
+                1,
+                Lambda::call(0, vec![Lambda::call(0, vec![Lambda::var(1)])]),
+                None,
+            ),
+            None,
+        );
+        assert_eq!(
+            format_lambda_pretty(&numeral, &bindings, 80),
+            "f(x(f.(f.(x))))"
+        );
+        assert_eq!(
+            format_lambda_pretty(&numeral, &bindings, 13),
+            "f(\n  x(\n    f.(f.(x))\n  )\n)"
+        );
+        let text = format_lambda_pretty(&numeral, &bindings, 1);
+        assert!(text.lines().count() > 5);
+        round_trip_text(&text, &numeral, &bindings);
+    }
+
+    // indices 0..6 are used for binders, 6 and 7 are the free variables
+    const NAMES: [&str; 8] = ["a", "a", "a'", "a_1", "letter", "5", "y", "z"];
+
+    #[test]
+    fn random_terms_round_trip() {
+        let bindings = names(&NAMES);
+        let mut generator = Generator::new(0x2545_f491_4f6c_dd1d, (0..6).collect());
+        generator.free = vec![6, 7];
+        for _ in 0..2000 {
+            round_trip(&generator.term(), &bindings);
+        }
+    }
+
+    #[test]
+    fn deep_numeral_round_trips() {
+        let text = format!("f(x({}x{}))", "f.(".repeat(100_000), ")".repeat(100_000));
+        let (lambda, bindings) = parse_program(&text).unwrap();
+        assert_eq!(format_lambda(&lambda, &bindings), text);
+        let (parsed, parsed_bindings) = parse_program(&format_lambda(&lambda, &bindings)).unwrap();
+        assert!(alpha_equivalent(
+            &lambda,
+            &bindings,
+            &parsed,
+            &parsed_bindings
+        ));
+    }
 }
@@ -0,0 +1,459 @@
+//! JSON exchange format for terms.
+//!
+//! A program is an object with the names of all bindings and the term itself:
+//!
+//! ```json
+//! { "bindings": ["f", "x"], "term": <term> }
+//! ```
+//!
+//! Terms refer to bindings by their position in `bindings`. Names do not have to be unique,
+//! a variable refers to the innermost definition with the same index. A term is one of
+//!
+//! - `{ "kind": "variable", "index": 1 }`
+//! - `{ "kind": "definition", "binder": 0, "body": <term>, "parameter": <term> }`,
+//!   where `parameter` is optional and is the argument the definition is applied to
+//! - `{ "kind": "call", "function": 0, "arguments": [<term>, ...] }`,
+//!   with at least one argument
+//!
+//! The output is not indented, as the indentation of deeply nested terms would grow
+//! quadratically.
+
+use crate::Lambda;
+
+/// Writes `lambda` as a term of the JSON format.
+fn write_term(lambda: &Lambda, json: &mut String) {
+    enum Work<'l> {
+        Visit(&'l Lambda),
+        Text(&'static str),
+    }
+    let mut work = vec![Work::Visit(lambda)];
+    while let Some(item) = work.pop() {
+        let lambda = match item {
+            Work::Visit(lambda) => lambda,
+            Work::Text(text) => {
+                json.push_str(text);
+                continue;
+            }
+        };
+        match lambda {
+            Lambda::Variable(value) => {
+                json.push_str(&format!(r#"{{"kind":"variable","index":{value}}}"#));
+            }
+            Lambda::Definition {
+                name_index,
+                body,
+                parameter,
+            } => {
+                json.push_str(&format!(
+                    r#"{{"kind":"definition","binder":{name_index},"body":"#
+                ));
+                work.push(Work::Text("}"));
+                if let Some(parameter) = parameter {
+                    work.push(Work::Visit(parameter));
+                    work.push(Work::Text(r#","parameter":"#));
+                }
+                work.push(Work::Visit(body));
+            }
+            Lambda::Call {
+                name_index,
+                parameters,
+            } => {
+                json.push_str(&format!(
+                    r#"{{"kind":"call","function":{name_index},"arguments":["#
+                ));
+                work.push(Work::Text("]}"));
+                for (position, parameter) in parameters.iter().enumerate().rev() {
+                    work.push(Work::Visit(parameter));
+                    if position > 0 {
+                        work.push(Work::Text(","));
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn lambda_to_json(lambda: &Lambda, bindings: &[String]) -> String {
+    let names: Vec<String> = bindings
+        .iter()
+        .map(|name| serde_json::to_string(name).unwrap())
+        .collect();
+    let mut json = format!(r#"{{"bindings":[{}],"term":"#, names.join(","));
+    write_term(lambda, &mut json);
+    json.push('}');
+    json
+}
+
+/// A JSON value. Arrays and objects refer to the values they contain by their position in
+/// the list of all values, so that no value owns a deeply nested tree.
+enum Value {
+    Number(usize),
+    String(String),
+    Array(Vec<usize>),
+    Object(Vec<(String, usize)>),
+    /// `null`, and also booleans and numbers that are not indices, which no field accepts
+    Other,
+}
+
+enum Open {
+    Array(Vec<usize>),
+    /// the fields read so far and the key of the value being read
+    Object(Vec<(String, usize)>, String),
+}
+
+struct Reader<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        let found = self.text[self.position..].starts_with(c);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn error(&self, expected: &str) -> String {
+        format!("expected {expected} at byte {}", self.position)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        let rest = &self.text[self.position..];
+        if !rest.starts_with('"') {
+            return Err(self.error("a string"));
+        }
+        let mut escaped = false;
+        let length = rest[1..].find(|c| {
+            let end = c == '"' && !escaped;
+            escaped = c == '\\' && !escaped;
+            end
+        });
+        let Some(length) = length else {
+            return Err(self.error("the end of the string"));
+        };
+        let string = serde_json::from_str(&rest[..length + 2]).map_err(|e| e.to_string())?;
+        self.position += length + 2;
+        Ok(string)
+    }
+
+    fn key(&mut self) -> Result<String, String> {
+        let key = self.string()?;
+        if !self.eat(':') {
+            return Err(self.error("`:`"));
+        }
+        Ok(key)
+    }
+
+    /// Reads a scalar, or returns `None` after opening an array or object.
+    fn scalar_or_open(&mut self, open: &mut Vec<Open>) -> Result<Option<Value>, String> {
+        if self.eat('[') {
+            if self.eat(']') {
+                return Ok(Some(Value::Array(Vec::new())));
+            }
+            open.push(Open::Array(Vec::new()));
+            return Ok(None);
+        }
+        if self.eat('{') {
+            if self.eat('}') {
+                return Ok(Some(Value::Object(Vec::new())));
+            }
+            open.push(Open::Object(Vec::new(), self.key()?));
+            return Ok(None);
+        }
+        if self.text[self.position..].starts_with('"') {
+            return self.string().map(|string| Some(Value::String(string)));
+        }
+        let rest = &self.text[self.position..];
+        let length = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && !"+-.".contains(c))
+            .unwrap_or(rest.len());
+        let scalar = &rest[..length];
+        let value = if let Ok(number) = scalar.parse() {
+            Value::Number(number)
+        } else if ["null", "true", "false"].contains(&scalar)
+            || serde_json::from_str::<f64>(scalar).is_ok()
+        {
+            Value::Other
+        } else {
+            return Err(self.error("a value"));
+        };
+        self.position += length;
+        Ok(Some(value))
+    }
+
+    /// Reads the whole text as one value and returns all values with the position of it.
+    fn read(mut self) -> Result<(Vec<Value>, usize), String> {
+        let mut values = Vec::new();
+        let mut open = Vec::new();
+        loop {
+            let Some(mut value) = self.scalar_or_open(&mut open)? else {
+                continue;
+            };
+            // add the value to the innermost open array or object, closing it if it ends
+            loop {
+                values.push(value);
+                let index = values.len() - 1;
+                match open.last_mut() {
+                    None => {
+                        self.skip_whitespace();
+                        if self.position < self.text.len() {
+                            return Err(self.error("the end of the input"));
+                        }
+                        return Ok((values, index));
+                    }
+                    Some(Open::Array(items)) => {
+                        items.push(index);
+                        if self.eat(',') {
+                            break;
+                        }
+                        if !self.eat(']') {
+                            return Err(self.error("`,` or `]`"));
+                        }
+                        let Some(Open::Array(items)) = open.pop() else {
+                            unreachable!()
+                        };
+                        value = Value::Array(items);
+                    }
+                    Some(Open::Object(fields, key)) => {
+                        fields.push((std::mem::take(key), index));
+                        if self.eat(',') {
+                            *key = self.key()?;
+                            break;
+                        }
+                        if !self.eat('}') {
+                            return Err(self.error("`,` or `}`"));
+                        }
+                        let Some(Open::Object(fields, _)) = open.pop() else {
+                            unreachable!()
+                        };
+                        value = Value::Object(fields);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn object(values: &[Value], index: usize) -> Result<&[(String, usize)], String> {
+    match &values[index] {
+        Value::Object(fields) => Ok(fields),
+        _ => Err("expected an object".to_owned()),
+    }
+}
+
+/// Checks that `fields` has only the fields `allowed`, each once.
+fn check_fields(fields: &[(String, usize)], allowed: &[&str]) -> Result<(), String> {
+    for (position, (key, _)) in fields.iter().enumerate() {
+        if !allowed.contains(&key.as_str()) {
+            return Err(format!(
+                "unknown field `{key}`, expected one of {}",
+                allowed.join(", ")
+            ));
+        }
+        if fields[..position].iter().any(|(other, _)| other == key) {
+            return Err(format!("duplicate field `{key}`"));
+        }
+    }
+    Ok(())
+}
+
+fn field(fields: &[(String, usize)], key: &str) -> Result<usize, String> {
+    optional_field(fields, key).ok_or(format!("missing field `{key}`"))
+}
+
+fn optional_field(fields: &[(String, usize)], key: &str) -> Option<usize> {
+    fields
+        .iter()
+        .find(|(other, _)| other == key)
+        .map(|(_, index)| *index)
+}
+
+fn to_lambda(values: &[Value], root: usize, bindings: &[String]) -> Result<Lambda, String> {
+    enum Work {
+        Visit(usize),
+        Definition(usize, bool),
+        Call(usize, usize),
+    }
+    let binding = |index: usize| {
+        let Value::Number(index) = values[index] else {
+            return Err("expected a binding index".to_owned());
+        };
+        if index < bindings.len() {
+            Ok(index)
+        } else {
+            Err(format!(
+                "binding index {index} is out of range, there are only {} bindings",
+                bindings.len()
+            ))
+        }
+    };
+    let mut work = vec![Work::Visit(root)];
+    let mut done = Vec::new();
+    while let Some(item) = work.pop() {
+        match item {
+            Work::Visit(index) => {
+                let fields = object(values, index)?;
+                let kind = match &values[field(fields, "kind")?] {
+                    Value::String(kind) => kind.as_str(),
+                    _ => return Err("expected the kind of the term as a string".to_owned()),
+                };
+                match kind {
+                    "variable" => {
+                        check_fields(fields, &["kind", "index"])?;
+                        done.push(Lambda::var(binding(field(fields, "index")?)?));
+                    }
+                    "definition" => {
+                        check_fields(fields, &["kind", "binder", "body", "parameter"])?;
+                        let parameter = optional_field(fields, "parameter")
+                            .filter(|parameter| !matches!(values[*parameter], Value::Other));
+                        work.push(Work::Definition(
+                            binding(field(fields, "binder")?)?,
+                            parameter.is_some(),
+                        ));
+                        work.push(Work::Visit(field(fields, "body")?));
+                        work.extend(parameter.map(Work::Visit));
+                    }
+                    "call" => {
+                        check_fields(fields, &["kind", "function", "arguments"])?;
+                        let function = binding(field(fields, "function")?)?;
+                        let Value::Array(arguments) = &values[field(fields, "arguments")?] else {
+                            return Err("expected the arguments as an array".to_owned());
+                        };
+                        if arguments.is_empty() {
+                            return Err(format!("call of binding {function} has no arguments"));
+                        }
+                        work.push(Work::Call(function, arguments.len()));
+                        work.extend(arguments.iter().rev().map(|index| Work::Visit(*index)));
+                    }
+                    _ => {
+                        return Err(format!(
+                            "unknown kind `{kind}`, expected one of variable, definition, call"
+                        ))
+                    }
+                }
+            }
+            Work::Definition(name_index, has_parameter) => {
+                let body = done.pop().unwrap();
+                let parameter = has_parameter.then(|| done.pop().unwrap());
+                done.push(Lambda::def(name_index, body, parameter));
+            }
+            Work::Call(name_index, count) => {
+                let parameters = done.split_off(done.len() - count);
+                done.push(Lambda::call(name_index, parameters));
+            }
+        }
+    }
+    Ok(done.pop().unwrap())
+}
+
+pub(crate) fn json_to_lambda(text: &str) -> Result<(Lambda, Vec<String>), String> {
+    let (values, root) = Reader { text, position: 0 }.read()?;
+    let fields = object(&values, root)?;
+    check_fields(fields, &["bindings", "term"])?;
+    let Value::Array(names) = &values[field(fields, "bindings")?] else {
+        return Err("expected the bindings as an array".to_owned());
+    };
+    let bindings = names
+        .iter()
+        .map(|index| match &values[*index] {
+            Value::String(name) => Ok(name.clone()),
+            _ => Err("expected the name of a binding as a string".to_owned()),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let lambda = to_lambda(&values, field(fields, "term")?, &bindings)?;
+    Ok((lambda, bindings))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parser::parse_program, Lambda};
+
+    use super::{json_to_lambda, lambda_to_json};
+
+    #[test]
+    fn schema() {
+        let (lambda, bindings) = parse_program("a(a.b).c(c)").unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&lambda_to_json(&lambda, &bindings)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "bindings": ["c", "a", "b"],
+                "term": {
+                    "kind": "definition",
+                    "binder": 1,
+                    "body": {
+                        "kind": "call",
+                        "function": 1,
+                        "arguments": [{ "kind": "variable", "index": 2 }]
+                    },
+                    "parameter": {
+                        "kind": "definition",
+                        "binder": 0,
+                        "body": { "kind": "variable", "index": 0 }
+                    }
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let (lambda, bindings) = parse_program("a(b(c(a.b.c))).d(e(e)).5.3").unwrap();
+        let json = lambda_to_json(&lambda, &bindings);
+        assert_eq!(json_to_lambda(&json), Ok((lambda, bindings)));
+    }
+
+    #[test]
+    fn deeply_nested_term() {
+        let mut lambda = Lambda::var(1);
+        for _ in 0..100_000 {
+            lambda = Lambda::call(0, vec![lambda]);
+        }
+        let bindings = vec!["f".to_string(), "x".to_string()];
+        let json = lambda_to_json(&lambda, &bindings);
+        assert_eq!(json_to_lambda(&json), Ok((lambda, bindings)));
+    }
+
+    #[test]
+    fn index_out_of_range() {
+        let json = r#"{ "bindings": ["x"], "term": { "kind": "variable", "index": 1 } }"#;
+        assert_eq!(
+            json_to_lambda(json),
+            Err("binding index 1 is out of range, there are only 1 bindings".to_string())
+        );
+    }
+
+    #[test]
+    fn invalid_programs() {
+        for json in [
+            r#"{ "bindings": [], "term": { "kind": "variable", "index": 0 }"#,
+            r#"{ "bindings": ["x"], "term": { "kind": "variable", "index": 0 } } x"#,
+            r#"{ "bindings": ["x"], "term": { "kind": "variable", "index": 0, "x": 1 } }"#,
+            r#"{ "bindings": ["x"], "term": { "kind": "lambda", "index": 0 } }"#,
+            r#"{ "bindings": ["x"], "term": { "kind": "variable", "index": 0.5 } }"#,
+            r#"{ "bindings": ["x"] }"#,
+        ] {
+            assert!(json_to_lambda(json).is_err(), "{json}");
+        }
+        let escaped =
+            r#"{ "bindings": ["\"x\u0027"], "term": { "kind": "variable", "index": 0 } }"#;
+        assert_eq!(json_to_lambda(escaped).unwrap().1, vec!["\"x'".to_string()]);
+    }
+
+    #[test]
+    fn call_without_arguments() {
+        let json =
+            r#"{ "bindings": ["f"], "term": { "kind": "call", "function": 0, "arguments": [] } }"#;
+        assert!(json_to_lambda(json).is_err());
+    }
+}
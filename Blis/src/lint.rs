@@ -0,0 +1,337 @@
+//! Warnings about programs that are valid, but probably not what was meant.
+//!
+//! The lints run on the parsed term, locations are the spans the parser records for the
+//! binders written in the source. Binders that the compiler introduces, like the helpers of
+//! `letrec`, have no span and are never reported, nor are names starting with `_`.
+
+use std::collections::HashSet;
+
+use crate::{helpers::collect_free, Lambda};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Lint {
+    UnusedLet,
+    UnusedParameter,
+    Shadowing,
+    Typo,
+}
+
+impl Lint {
+    pub(crate) const ALL: [Lint; 4] = [
+        Lint::UnusedLet,
+        Lint::UnusedParameter,
+        Lint::Shadowing,
+        Lint::Typo,
+    ];
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Lint::UnusedLet => "unused-let",
+            Lint::UnusedParameter => "unused-parameter",
+            Lint::Shadowing => "shadowing",
+            Lint::Typo => "typo",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        Lint::ALL.into_iter().find(|lint| lint.name() == name)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Warning {
+    pub(crate) lint: Lint,
+    /// the name the warning is about
+    pub(crate) name: String,
+    pub(crate) message: String,
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + (a != *b) as usize;
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+struct Linter<'a> {
+    bindings: &'a [String],
+    spans: &'a [Option<usize>],
+    scope: Vec<usize>,
+    used: HashSet<usize>,
+    definitions: Vec<usize>,
+    /// pairs of an outer and an inner binder with the same name, both written in the source
+    shadowed: Vec<(usize, usize)>,
+}
+
+impl Linter<'_> {
+    fn walk(&mut self, lambda: &Lambda) {
+        enum Work<'l> {
+            Visit(&'l Lambda),
+            Body(usize, &'l Lambda),
+            PopScope,
+        }
+        let mut work = vec![Work::Visit(lambda)];
+        while let Some(item) = work.pop() {
+            match item {
+                Work::Visit(Lambda::Variable(value)) => {
+                    self.used.insert(*value);
+                }
+                Work::Visit(Lambda::Definition {
+                    name_index,
+                    body,
+                    parameter,
+                }) => {
+                    work.push(Work::Body(*name_index, body));
+                    work.extend(parameter.as_deref().map(Work::Visit));
+                }
+                Work::Visit(Lambda::Call {
+                    name_index,
+                    parameters,
+                }) => {
+                    self.used.insert(*name_index);
+                    work.extend(parameters.iter().rev().map(Work::Visit));
+                }
+                Work::Body(name_index, body) => {
+                    let name = &self.bindings[name_index];
+                    let outer = self.scope.iter().rev().find(|index| {
+                        self.bindings[**index] == *name && self.spans[**index].is_some()
+                    });
+                    if let (Some(outer), Some(_)) = (outer, self.spans[name_index]) {
+                        self.shadowed.push((*outer, name_index));
+                    }
+                    self.definitions.push(name_index);
+                    self.scope.push(name_index);
+                    work.push(Work::PopScope);
+                    work.push(Work::Visit(body));
+                }
+                Work::PopScope => {
+                    self.scope.pop();
+                }
+            }
+        }
+    }
+}
+
+/// Lints the program compiled from `source`, where `spans` are the offsets in `source` of
+/// the binders written there, by binding.
+pub(crate) fn lint(
+    source: &str,
+    lambda: &Lambda,
+    bindings: &[String],
+    spans: &[Option<usize>],
+) -> Vec<Warning> {
+    let mut linter = Linter {
+        bindings,
+        spans,
+        scope: Vec::new(),
+        used: HashSet::new(),
+        definitions: Vec::new(),
+        shadowed: Vec::new(),
+    };
+    linter.walk(lambda);
+    let line = |span: usize| source[..span].matches('\n').count() + 1;
+    let mut warnings = Vec::new();
+    // the compiler may copy a binder, which is reported once
+    let mut reported = HashSet::new();
+    for index in &linter.definitions {
+        let name = &bindings[*index];
+        let Some(span) = spans[*index] else {
+            continue;
+        };
+        if linter.used.contains(index) || name.starts_with('_') || !reported.insert(span) {
+            continue;
+        }
+        let keyword = source[..span]
+            .trim_end()
+            .rsplit(|c: char| !c.is_alphanumeric())
+            .next();
+        let (lint, kind) = if matches!(keyword, Some("let" | "letrec" | "and")) {
+            (Lint::UnusedLet, "definition")
+        } else {
+            (Lint::UnusedParameter, "parameter")
+        };
+        warnings.push(Warning {
+            lint,
+            name: name.clone(),
+            message: format!("unused {kind} `{name}` at line {}", line(span)),
+        });
+    }
+    let mut reported = HashSet::new();
+    for (outer, inner) in &linter.shadowed {
+        let name = &bindings[*outer];
+        let (outer, inner) = (spans[*outer].unwrap(), spans[*inner].unwrap());
+        if !reported.insert((outer, inner)) {
+            continue;
+        }
+        warnings.push(Warning {
+            lint: Lint::Shadowing,
+            name: name.clone(),
+            message: format!(
+                "`{name}` at line {} shadows the definition at line {}",
+                line(inner),
+                line(outer)
+            ),
+        });
+    }
+    let mut free = Vec::new();
+    collect_free(lambda, &mut Vec::new(), &mut free);
+    for index in free {
+        let name = &bindings[index];
+        let similar = linter
+            .definitions
+            .iter()
+            .map(|definition| &bindings[*definition])
+            .find(|bound| edit_distance(name, bound) == 1);
+        if let (true, Some(bound)) = (name.chars().count() >= 3, similar) {
+            warnings.push(Warning {
+                lint: Lint::Typo,
+                name: name.clone(),
+                message: format!("free variable `{name}` looks like a typo of `{bound}`"),
+            });
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compiler::compile_with_spans, parser::parse_program_with_spans};
+
+    use super::{edit_distance, lint, Lint};
+
+    fn lints(source: &str) -> Vec<(Lint, String)> {
+        let (compiled, _) = compile_with_spans(source).unwrap();
+        let (lambda, bindings, spans) = parse_program_with_spans(&compiled).unwrap();
+        lint(source, &lambda, &bindings, &spans)
+            .into_iter()
+            .map(|warning| (warning.lint, warning.message))
+            .collect()
+    }
+
+    #[test]
+    fn clean_program() {
+        let source = "
+        let succ n,f,x(f.(n.f.x));
+        let zero _f,x(x);
+        succ.zero";
+        assert_eq!(lints(source), Vec::new());
+    }
+
+    #[test]
+    fn unused_let() {
+        let source = "
+        let zero _f,x(x);
+        let one f,x(f.x);
+        zero";
+        assert_eq!(
+            lints(source),
+            vec![(Lint::UnusedLet, "unused definition `one` at line 3".into())]
+        );
+    }
+
+    #[test]
+    fn unused_parameter() {
+        let source = "
+        let zero _f,x(x);
+        let true t,f(
+            t
+        );
+        true.zero";
+        assert_eq!(
+            lints(source),
+            vec![(
+                Lint::UnusedParameter,
+                "unused parameter `f` at line 3".into()
+            )]
+        );
+        assert_eq!(lints("t,_f(t)"), Vec::new());
+    }
+
+    #[test]
+    fn shadowing() {
+        let source = "
+        let x y(y);
+        f(
+            f.(x(x))
+        ).(x)";
+        assert_eq!(
+            lints(source),
+            vec![(
+                Lint::Shadowing,
+                "`x` at line 4 shadows the definition at line 2".into()
+            )]
+        );
+    }
+
+    #[test]
+    fn typo() {
+        let source = "
+        let succ n,f,x(f.(n.f.x));
+        succ.suc";
+        assert_eq!(
+            lints(source),
+            vec![(
+                Lint::Typo,
+                "free variable `suc` looks like a typo of `succ`".into()
+            )]
+        );
+    }
+
+    #[test]
+    fn every_unused_binder_is_reported() {
+        let source = "
+        let first x,y(x);
+        let second x,y(x);
+        first.second";
+        assert_eq!(
+            lints(source),
+            vec![
+                (
+                    Lint::UnusedParameter,
+                    "unused parameter `y` at line 2".into()
+                ),
+                (
+                    Lint::UnusedParameter,
+                    "unused parameter `y` at line 3".into()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn literals_are_not_binders_or_uses() {
+        let source = "
+        let used x(x);
+        let unused x(\"let x(x)\");
+        used.unused";
+        assert_eq!(
+            lints(source),
+            vec![(
+                Lint::UnusedParameter,
+                "unused parameter `x` at line 3".into()
+            )]
+        );
+    }
+
+    #[test]
+    fn generated_names_are_ignored() {
+        let source = "
+        letrec a b(b.a) and c d(d.c);
+        a.c";
+        assert_eq!(lints(source), Vec::new());
+    }
+
+    #[test]
+    fn distance() {
+        assert_eq!(edit_distance("succ", "suc"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+}
@@ -0,0 +1,214 @@
+//! Character and string literals. `'a'` is the Church numeral of the code point 97,
+//! `"ab"` the Church list `_c,_n(_c.'a'.(_c.'b'._n))` of its characters.
+//! The binders start with `_`, so that they are not linted as unused.
+
+use crate::Lambda;
+
+// Both are written in one pass: building them by wrapping the previous string would be
+// quadratic in the code point.
+fn push_numeral(string: &mut String, code_point: u32) {
+    let depth = code_point as usize;
+    string.push_str("_f,_x(");
+    string.push_str(&"_f.(".repeat(depth));
+    string.push_str("_x");
+    string.push_str(&")".repeat(depth));
+    string.push(')');
+}
+
+fn push_list(string: &mut String, characters: &[char]) {
+    string.push_str("_c,_n(");
+    for &c in characters {
+        string.push_str("_c.(");
+        push_numeral(string, c as u32);
+        string.push_str(").(");
+    }
+    string.push_str("_n");
+    string.push_str(&")".repeat(characters.len()));
+    string.push(')');
+}
+
+/// Reads the character at the start of `text`, which may be an escape sequence, and
+/// returns it with the number of bytes it took.
+fn read_character(text: &str) -> Result<(char, usize), String> {
+    let mut chars = text.chars();
+    let c = chars.next().ok_or("unterminated literal")?;
+    if c != '\\' {
+        return Ok((c, c.len_utf8()));
+    }
+    let escaped = chars.next().ok_or("unterminated literal")?;
+    let c = match escaped {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        '0' => '\0',
+        '\\' | '\'' | '"' => escaped,
+        'u' => {
+            let digits = text[2..]
+                .strip_prefix('{')
+                .and_then(|rest| rest.split_once('}'))
+                .map(|(digits, _)| digits)
+                .ok_or("expected `\\u{...}`")?;
+            let c = u32::from_str_radix(digits, 16)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| format!("`\\u{{{digits}}}` is not a character"))?;
+            return Ok((c, "\\u{}".len() + digits.len()));
+        }
+        _ => return Err(format!("unknown escape sequence `\\{escaped}`")),
+    };
+    Ok((c, 2))
+}
+
+/// Replaces the character and string literals in `text` by their encodings.
+pub(crate) fn replace_literals(text: &str) -> Result<String, String> {
+    let mut string = String::new();
+    let mut index = 0;
+    while index < text.len() {
+        let rest = &text[index..];
+        let c = rest.chars().next().unwrap();
+        // a quote after a name is part of it, like in `x'`
+        let starts_literal = c == '"'
+            || (c == '\''
+                && !string.ends_with(|c: char| !"().,;:=".contains(c) && !c.is_whitespace()));
+        if !starts_literal {
+            string.push(c);
+            index += c.len_utf8();
+            continue;
+        }
+        let mut characters = Vec::new();
+        let mut length = 1;
+        loop {
+            if rest[length..].starts_with(c) {
+                length += 1;
+                break;
+            }
+            let (character, character_length) = read_character(&rest[length..])?;
+            characters.push(character);
+            length += character_length;
+        }
+        if c == '"' {
+            push_list(&mut string, &characters);
+        } else if let [character] = characters.as_slice() {
+            push_numeral(&mut string, *character as u32);
+        } else {
+            return Err(format!(
+                "a character literal needs exactly one character, found `{}`",
+                &rest[..length]
+            ));
+        }
+        index += length;
+    }
+    Ok(string)
+}
+
+/// Decodes a Church numeral in normal form.
+pub(crate) fn decode_numeral(lambda: &Lambda) -> Option<u32> {
+    let Lambda::Definition {
+        name_index: f,
+        body,
+        parameter: None,
+    } = lambda
+    else {
+        return None;
+    };
+    let Lambda::Definition {
+        name_index: x,
+        body,
+        parameter: None,
+    } = body.as_ref()
+    else {
+        return None;
+    };
+    let mut count = 0;
+    let mut current = body.as_ref();
+    loop {
+        match current {
+            Lambda::Variable(value) if value == x => return Some(count),
+            Lambda::Call {
+                name_index,
+                parameters,
+            } if name_index == f && parameters.len() == 1 => {
+                count += 1;
+                current = &parameters[0];
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Decodes a normal form that is a non-empty Church list of code points.
+/// The empty list is not decoded, as it is also the numeral zero.
+pub(crate) fn decode_string(lambda: &Lambda) -> Option<String> {
+    let Lambda::Definition {
+        name_index: c,
+        body,
+        parameter: None,
+    } = lambda
+    else {
+        return None;
+    };
+    let Lambda::Definition {
+        name_index: n,
+        body,
+        parameter: None,
+    } = body.as_ref()
+    else {
+        return None;
+    };
+    let mut string = String::new();
+    let mut current = body.as_ref();
+    loop {
+        match current {
+            Lambda::Variable(value) if value == n && !string.is_empty() => return Some(string),
+            Lambda::Call {
+                name_index,
+                parameters,
+            } if name_index == c && parameters.len() == 2 => {
+                string.push(char::from_u32(decode_numeral(&parameters[0])?)?);
+                current = &parameters[1];
+            }
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compiler::compile, parser::parse_program};
+
+    use super::{decode_string, replace_literals};
+
+    #[test]
+    fn character() {
+        assert_eq!(
+            replace_literals("succ.'\\u{2}'"),
+            Ok("succ._f,_x(_f.(_f.(_x)))".to_string())
+        );
+        assert_eq!(replace_literals("x'.(y')"), Ok("x'.(y')".to_string()));
+        assert!(replace_literals("'ab'").is_err());
+        assert!(replace_literals("'\\q'").is_err());
+        let emoji = replace_literals("'😀'").unwrap();
+        assert_eq!(emoji.len(), "_f,_x(_x)".len() + 5 * 0x1F600);
+    }
+
+    #[test]
+    fn string() {
+        assert_eq!(
+            replace_literals("\"\\0\\0\""),
+            Ok("_c,_n(_c.(_f,_x(_x)).(_c.(_f,_x(_x)).(_n)))".to_string())
+        );
+        assert_eq!(replace_literals("\"\""), Ok("_c,_n(_n)".to_string()));
+        assert!(replace_literals("\"abc").is_err());
+    }
+
+    #[test]
+    fn decode() {
+        for text in ["hi", "a (b); \"c\"\n", "é"] {
+            let (lambda, _bindings) =
+                parse_program(&compile(&format!("{text:?}")).unwrap()).unwrap();
+            assert_eq!(decode_string(&lambda).as_deref(), Some(text));
+        }
+        let (zero, _bindings) = parse_program("f(x(x))").unwrap();
+        assert_eq!(decode_string(&zero), None);
+    }
+}
@@ -1,18 +1,57 @@
-use std::{collections::VecDeque, env, fmt::Display, fs};
+use std::{
+    collections::VecDeque,
+    env,
+    fmt::Display,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+};
 
-use compiler::compile;
-
-use helpers::format_lambda;
-use parser::{parse_program, Binder};
-use reducer::full_reduce;
+use arena::Arena;
+use assertions::run_tests;
+use blc::{bits_to_bytes, blc_to_lambda, lambda_to_blc, read_bits};
+use compiler::{compile_with_spans, Assertion};
+use confluence::check_confluence;
+use dot::{lambda_to_dot, trace_to_dot};
+use filter::run_filter;
+use generate::print_terms;
+use helpers::format_normal_form;
+use json::{json_to_lambda, lambda_to_json};
+use lint::{lint, Lint};
+use memo::{default_path, normalize_definitions, Cache};
+use optimize::{optimize, size};
+use parser::{parse_program_with_spans, Binder};
+use reducer::{try_full_reduce, try_full_reduce_visiting};
+use ski::{format_combinators, lambda_to_combinators, reduce_combinators, Basis};
+use snapshot::run_snapshots;
+use types::{format_type, infer_type};
 
+mod arena;
+mod assertions;
+mod blc;
 mod compiler;
+mod confluence;
+mod data;
+mod dot;
+mod filter;
+mod generate;
 mod helpers;
+mod json;
+mod lint;
+mod literals;
+mod memo;
+mod operators;
+mod optimize;
 mod parser;
+mod pretty;
 mod reducer;
+mod ski;
+mod snapshot;
+mod types;
 
 // make this copy-able
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug)]
 enum Lambda {
     Variable(usize),
     Definition {
@@ -58,29 +97,181 @@ impl Display for Lambda {
     }
 }
 
+impl Clone for Lambda {
+    fn clone(&self) -> Self {
+        enum Work<'a> {
+            Visit(&'a Lambda),
+            Definition(usize, bool),
+            Call(usize, usize),
+        }
+        let mut work = vec![Work::Visit(self)];
+        let mut done = Vec::new();
+        while let Some(item) = work.pop() {
+            match item {
+                Work::Visit(Lambda::Variable(value)) => done.push(Lambda::Variable(*value)),
+                Work::Visit(Lambda::Definition {
+                    name_index,
+                    body,
+                    parameter,
+                }) => {
+                    work.push(Work::Definition(*name_index, parameter.is_some()));
+                    work.push(Work::Visit(body));
+                    if let Some(parameter) = parameter {
+                        work.push(Work::Visit(parameter));
+                    }
+                }
+                Work::Visit(Lambda::Call {
+                    name_index,
+                    parameters,
+                }) => {
+                    work.push(Work::Call(*name_index, parameters.len()));
+                    work.extend(parameters.iter().rev().map(Work::Visit));
+                }
+                Work::Definition(name_index, has_parameter) => {
+                    let body = done.pop().unwrap();
+                    let parameter = has_parameter.then(|| done.pop().unwrap());
+                    done.push(Lambda::def(name_index, body, parameter));
+                }
+                Work::Call(name_index, count) => {
+                    let parameters = done.split_off(done.len() - count);
+                    done.push(Lambda::call(name_index, parameters));
+                }
+            }
+        }
+        done.pop().unwrap()
+    }
+}
+
+impl PartialEq for Lambda {
+    fn eq(&self, other: &Self) -> bool {
+        let mut pairs = vec![(self, other)];
+        while let Some(pair) = pairs.pop() {
+            match pair {
+                (Lambda::Variable(a), Lambda::Variable(b)) if a == b => {}
+                (
+                    Lambda::Definition {
+                        name_index: a_name,
+                        body: a_body,
+                        parameter: a_parameter,
+                    },
+                    Lambda::Definition {
+                        name_index: b_name,
+                        body: b_body,
+                        parameter: b_parameter,
+                    },
+                ) if a_name == b_name => {
+                    match (a_parameter, b_parameter) {
+                        (None, None) => {}
+                        (Some(a), Some(b)) => pairs.push((a, b)),
+                        _ => return false,
+                    }
+                    pairs.push((a_body, b_body));
+                }
+                (
+                    Lambda::Call {
+                        name_index: a_name,
+                        parameters: a_parameters,
+                    },
+                    Lambda::Call {
+                        name_index: b_name,
+                        parameters: b_parameters,
+                    },
+                ) if a_name == b_name && a_parameters.len() == b_parameters.len() => {
+                    pairs.extend(a_parameters.iter().zip(b_parameters));
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+impl Eq for Lambda {}
+
+impl Drop for Lambda {
+    fn drop(&mut self) {
+        if let Lambda::Variable(_) = self {
+            return;
+        }
+        // the children are moved onto the heap and dropped once they have no children left
+        let mut stack = Vec::new();
+        self.detach_children(&mut stack);
+        while let Some(mut lambda) = stack.pop() {
+            lambda.detach_children(&mut stack);
+        }
+    }
+}
+
+/// The parts of a term, taken out of it by `Lambda::into_parts`. As `Lambda` implements
+/// `Drop`, patterns cannot move them out of the term itself.
+pub(crate) enum Parts {
+    Variable(usize),
+    Definition {
+        name_index: usize,
+        body: Lambda,
+        parameter: Option<Lambda>,
+    },
+    Call {
+        name_index: usize,
+        parameters: VecDeque<Lambda>,
+    },
+}
+
 impl Lambda {
+    pub(crate) fn into_parts(mut self) -> Parts {
+        match &mut self {
+            Lambda::Variable(value) => Parts::Variable(*value),
+            Lambda::Definition {
+                name_index,
+                body,
+                parameter,
+            } => Parts::Definition {
+                name_index: *name_index,
+                body: std::mem::replace(body, Lambda::Variable(0)),
+                parameter: parameter.take().map(|parameter| *parameter),
+            },
+            Lambda::Call {
+                name_index,
+                parameters,
+            } => Parts::Call {
+                name_index: *name_index,
+                parameters: std::mem::take(parameters),
+            },
+        }
+    }
+
+    /// Moves the children of a definition or call onto `stack`, leaving variables behind.
+    fn detach_children(&mut self, stack: &mut Vec<Lambda>) {
+        match self {
+            Lambda::Variable(_) => {}
+            Lambda::Definition {
+                body, parameter, ..
+            } => {
+                stack.push(std::mem::replace(body, Lambda::Variable(0)));
+                stack.extend(parameter.take().map(|parameter| *parameter));
+            }
+            Lambda::Call { parameters, .. } => stack.extend(parameters.drain(..)),
+        }
+    }
+
     pub(crate) fn new_var(value: &str, binder: &mut Binder) -> Self {
-        let index = binder.find_index(value);
-        let index = if let Some(index) = index {
-            index
-        } else {
-            binder.new_binding(value.to_owned())
-        };
-        // println!("got index {index} for binding {value}");
+        let index = binder
+            .lookup(value)
+            .unwrap_or_else(|| binder.free_variable(value));
         Lambda::Variable(index)
     }
     pub(crate) fn new_call(
         function_name: &str,
         parameter: Vec<Lambda>,
         binder: &mut Binder,
-    ) -> Self {
+    ) -> Result<Self, String> {
         let name_index = binder
-            .find_index(function_name)
-            .unwrap_or_else(|| panic!("Unknown function name: {function_name}"));
-        Lambda::Call {
+            .lookup(function_name)
+            .ok_or(format!("unknown function name `{function_name}`"))?;
+        Ok(Lambda::Call {
             name_index,
             parameters: VecDeque::from(parameter),
-        }
+        })
     }
 
     pub(crate) fn var(name_index: usize) -> Self {
@@ -102,42 +293,421 @@ impl Lambda {
     }
 }
 
+const ITERATIONS: usize = 10000;
+
+/// A parsed program: the term, its bindings, the source offsets of the binders by binding
+/// and the assertions.
+type Program = (Lambda, Vec<String>, Vec<Option<usize>>, Vec<Assertion>);
+
+/// Compiles and parses `text`.
+fn load_program(text: &str) -> Result<Program, String> {
+    let (compiled, assertions) = compile_with_spans(text)?;
+    let (lambda, bindings, spans) = parse_program_with_spans(&compiled)?;
+    Ok((lambda, bindings, spans, assertions))
+}
+
+#[cfg(test)]
 fn run_program(text: &str) -> (Lambda, Vec<String>) {
-    let compiled = compile(text);
-    let (lambda, bindings) = parse_program(&compiled);
-    let bindings_clone = bindings.clone();
-    (full_reduce(lambda, 10000), bindings_clone)
+    let (lambda, mut bindings, _, _) = load_program(text).unwrap();
+    (
+        reducer::full_reduce(lambda, &mut bindings, ITERATIONS),
+        bindings,
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Emit {
+    Text,
+    Dot,
+    DotTrace,
+    Json,
+    Blc,
+    Blc8,
+    Ski,
+}
+
+impl Emit {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(Emit::Text),
+            "dot" => Some(Emit::Dot),
+            "dot-trace" => Some(Emit::DotTrace),
+            "json" => Some(Emit::Json),
+            "blc" => Some(Emit::Blc),
+            "blc8" => Some(Emit::Blc8),
+            "ski" => Some(Emit::Ski),
+            _ => None,
+        }
+    }
+}
+
+/// The command line options that decide how a program is run and what is printed.
+struct Options {
+    width: usize,
+    emit: Emit,
+    ski_backend: bool,
+    basis: Basis,
+    typecheck: bool,
+    /// the lints that are not reported
+    allowed: Vec<Lint>,
+    stats: bool,
+    /// the file the normal forms of definitions are kept in, `None` with `--no-cache`
+    cache: Option<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            width: 80,
+            emit: Emit::Text,
+            ski_backend: false,
+            basis: Basis::Ski,
+            typecheck: false,
+            // parameters that are not used are common in encodings like zero or false
+            allowed: vec![Lint::UnusedParameter],
+            stats: false,
+            cache: Some(default_path()),
+        }
+    }
+}
+
+/// Replaces closed top-level definitions by their normal forms, which are read from and
+/// written to the cache file.
+fn normalize_top_level(
+    lambda: Lambda,
+    bindings: &mut Vec<String>,
+    cache: Option<&Path>,
+    diagnostics: &mut impl Write,
+) -> (Lambda, memo::Stats) {
+    let Some(path) = cache else {
+        return (lambda, memo::Stats::default());
+    };
+    let mut cache = Cache::load(path);
+    let normalized = normalize_definitions(lambda, bindings, &mut cache);
+    if let Err(error) = cache.save() {
+        let _ = writeln!(diagnostics, "{error}");
+    }
+    normalized
+}
+
+/// Reads the program in `contents`, as binary lambda calculus or JSON if the name of `file`
+/// says so. Lints of source programs and the type with `--typecheck` go to `diagnostics`.
+fn read_program(
+    file: &str,
+    contents: Vec<u8>,
+    options: &Options,
+    diagnostics: &mut impl Write,
+) -> Result<(Lambda, Vec<String>), String> {
+    let (lambda, bindings) = if file.ends_with(".blc") {
+        blc_to_lambda(&read_bits(&contents))
+            .map_err(|error| format!("invalid binary lambda calculus: {error}"))?
+    } else {
+        let contents =
+            String::from_utf8(contents).map_err(|_| "the file is not valid UTF-8".to_owned())?;
+        if file.ends_with(".json") {
+            json_to_lambda(&contents).map_err(|error| format!("invalid JSON term: {error}"))?
+        } else {
+            let (lambda, bindings, spans, assertions) = load_program(&contents)?;
+            for warning in lint(&contents, &lambda, &bindings, &spans) {
+                // definitions that only the assertions use are not unused
+                let asserted = warning.lint == Lint::UnusedLet
+                    && assertions
+                        .iter()
+                        .any(|assertion| assertion.names.contains(&warning.name));
+                if !options.allowed.contains(&warning.lint) && !asserted {
+                    let (message, name) = (warning.message, warning.lint.name());
+                    let _ = writeln!(diagnostics, "warning: {message} [{name}]");
+                }
+            }
+            (lambda, bindings)
+        }
+    };
+    if options.typecheck {
+        let t = infer_type(&lambda, &bindings)?;
+        let _ = writeln!(diagnostics, "type: {}", format_type(&t));
+    }
+    Ok((lambda, bindings))
+}
+
+/// Runs the program in the file `file` with `contents` as `options` say, returning what is
+/// printed to stdout, or the error it fails with. With `--emit blc8`, that is the bits that
+/// are packed into bytes. Warnings, the type and the statistics go to `diagnostics`.
+fn run(
+    file: &str,
+    contents: Vec<u8>,
+    options: &Options,
+    diagnostics: &mut impl Write,
+) -> Result<String, String> {
+    let (lambda, mut bindings) = read_program(file, contents, options, diagnostics)?;
+    let not_reducible = || format!("not reducible in {ITERATIONS} iterations");
+    if options.ski_backend || options.emit == Emit::Ski {
+        let mut combinators = lambda_to_combinators(&lambda, options.basis);
+        if options.ski_backend {
+            combinators = reduce_combinators(&combinators, ITERATIONS).0;
+        }
+        return Ok(format!("{}\n", format_combinators(&combinators, &bindings)));
+    }
+    if options.emit == Emit::DotTrace {
+        let mut arena = Arena::default();
+        let root = arena.insert(&lambda);
+        let mut terms = Vec::new();
+        try_full_reduce_visiting(&mut arena, root, &mut bindings, ITERATIONS, |term| {
+            terms.push(term)
+        })
+        .ok_or_else(not_reducible)?;
+        return Ok(trace_to_dot(&arena, &terms, &bindings));
+    }
+    let unoptimized = options.stats.then(|| lambda.clone());
+    let size_before = size(&lambda);
+    let (lambda, optimize_stats) = optimize(lambda, &mut bindings);
+    let (lambda, memo_stats) =
+        normalize_top_level(lambda, &mut bindings, options.cache.as_deref(), diagnostics);
+    let result = match unoptimized {
+        None => try_full_reduce(lambda, &mut bindings, ITERATIONS).ok_or_else(not_reducible)?,
+        Some(unoptimized) => {
+            let size_after = size(&lambda);
+            let mut count_steps = |lambda| {
+                let mut arena = Arena::default();
+                let root = arena.insert(&lambda);
+                // the normal form itself is visited too
+                let mut steps = 0;
+                let result =
+                    try_full_reduce_visiting(&mut arena, root, &mut bindings, ITERATIONS, |_| {
+                        steps += 1
+                    })
+                    .ok_or_else(not_reducible)?;
+                Ok::<_, String>((arena.to_lambda(result), steps - 1))
+            };
+            let (_, steps_before) = count_steps(unoptimized)?;
+            let (result, steps_after) = count_steps(lambda)?;
+            // on stderr like the type, so that the result can be piped
+            let _ = writeln!(
+                diagnostics,
+                "definitions: {} removed, {} inlined, {} normalized once",
+                optimize_stats.removed, optimize_stats.inlined, memo_stats.normalized
+            );
+            let _ = writeln!(diagnostics, "size: {size_before} -> {size_after} nodes");
+            // the normal forms taken from the cache took steps in an earlier run
+            let _ = writeln!(
+                diagnostics,
+                "steps: {steps_before} -> {steps_after}, plus {} normalizing definitions ({} normal forms taken from the cache)",
+                memo_stats.steps, memo_stats.cached
+            );
+            result
+        }
+    };
+    Ok(match options.emit {
+        Emit::Text => format!(
+            "{}\n",
+            format_normal_form(&result, &bindings, options.width)
+        ),
+        Emit::Dot => lambda_to_dot(&result, &bindings),
+        Emit::DotTrace | Emit::Ski => unreachable!(),
+        Emit::Json => format!("{}\n", lambda_to_json(&result, &bindings)),
+        Emit::Blc => format!("{}\n", lambda_to_blc(&result)),
+        Emit::Blc8 => lambda_to_blc(&result),
+    })
 }
 
 fn print_usage() {
-    println!("==================================\n| Blis - Lambda Calculus Reducer |\n==================================\n\nWarning:\n    This is a research project and not meant for general use.\n    Therefore, the parser is not built very defensively and may produce false positives.\n    Proceed at your own risk.\n\nUsage:\n    blis.exe [Options or Args]\n\nArgs:\n    \"path/to/file\" - open the file and reduce the contained lambda calculus expression\n\nOptions:\n    --help         - show this message\n\n");
+    println!("==================================\n| Blis - Lambda Calculus Reducer |\n==================================\n\nWarning:\n    This is a research project and not meant for general use.\n    Therefore, the parser is not built very defensively and may produce false positives.\n    Proceed at your own risk.\n\nUsage:\n    blis.exe [Options or Args]\n    blis.exe test <paths> - check the `assert left == right;` and `assert_nf term == normal_form;`\n                            statements of the files and of the files in the directories\n    blis.exe snapshot <paths> [--bless]\n                          - compare the output of every file with the .expected file next to it,\n                            --bless writes the current output to the .expected files instead\n    blis.exe check-confluence <file>\n                          - reduce the file with several strategies, including random redex\n                            choices, and check that all that terminate reach the same normal form,\n                            exits with 2 if fewer than two of them terminate\n    blis.exe generate <seed> [count] [--simply-typed]\n                          - print count (default 1) random closed terms, one per line,\n                            --simply-typed only generates terms that have a normal form\n\nArgs:\n    \"path/to/file\" - open the file and reduce the contained lambda calculus expression\n                     files ending in .json are read as terms in the JSON format of --emit json\n                     files ending in .blc are read as binary lambda calculus, as bits or bytes\n\nOptions:\n    --help         - show this message\n    --width <n>    - break the printed result into lines of at most n columns (default 80)\n    --emit <kind>  - how to print the result:\n                       text      - Blis source (default)\n                       dot       - Graphviz syntax tree of the normal form\n                       dot-trace - Graphviz graph of every reduction step\n                       json      - bindings and term as JSON\n                       blc       - binary lambda calculus as a string of 0s and 1s\n                       blc8      - binary lambda calculus packed into bytes\n                       ski       - the program translated to combinators, without reducing it\n    --backend <b>  - how to reduce the program:\n                       lambda    - substitution on lambda terms in normal order (default)\n                       ski       - graph reduction of combinators, prints the combinator normal form\n    --combinators <c>\n                   - combinators used by --emit ski and --backend ski:\n                       ski       - S, K and I (default)\n                       skibcw    - additionally B, C and W for smaller output\n    --typecheck    - infer simple types before reducing, print the type to stderr\n                     and stop at the first type error\n    --allow <lint> - do not warn about a lint, can be repeated:\n                       unused-let, unused-parameter (allowed by default), shadowing, typo\n                     names starting with _ are never reported as unused\n    --warn <lint>  - warn about a lint that is allowed, like unused-parameter, can be repeated\n    --stats        - print to stderr how much dropping unused and inlining single use\n                     definitions shrank the program and how many steps the reduction took\n    --io           - apply the program to stdin as a list of byte numerals and write the\n                     resulting list to stdout as it is produced, stdin is read as the program needs it\n    --no-cache     - do not replace closed top-level definitions by their normal forms before\n                     reducing, which are otherwise kept in ~/.cache/blis/normal-forms,\n                     a closed definition without a normal form, like the Y combinator,\n                     is reduced for the whole step limit the first time it is seen\n\n");
 }
 
 fn main() {
-    let arg = env::args().nth(1);
-    if arg.is_none() {
+    match env::args().nth(1).as_deref() {
+        Some("test") => {
+            let paths: Vec<String> = env::args().skip(2).collect();
+            if paths.is_empty() {
+                println!("test expects files or directories with assertions");
+                return;
+            }
+            if !run_tests(&paths) {
+                process::exit(1);
+            }
+            return;
+        }
+        Some("check-confluence") => {
+            let Some(file) = env::args().nth(2) else {
+                println!("check-confluence expects a file");
+                return;
+            };
+            process::exit(check_confluence(&file));
+        }
+        Some("generate") => {
+            let (flags, numbers): (Vec<String>, Vec<String>) =
+                env::args().skip(2).partition(|arg| arg.starts_with("--"));
+            let simply_typed = match flags.as_slice() {
+                [] => false,
+                [flag] if flag == "--simply-typed" => true,
+                _ => {
+                    println!("generate only supports --simply-typed");
+                    return;
+                }
+            };
+            let numbers: Result<Vec<u64>, _> = numbers.iter().map(|n| n.parse()).collect();
+            match numbers.as_deref() {
+                Ok([seed]) => print_terms(*seed, 1, simply_typed),
+                Ok([seed, count]) => print_terms(*seed, *count as usize, simply_typed),
+                _ => println!("generate expects a seed and optionally a count"),
+            }
+            return;
+        }
+        Some("snapshot") => {
+            let (flags, paths): (Vec<String>, Vec<String>) =
+                env::args().skip(2).partition(|arg| arg.starts_with("--"));
+            let bless = match flags.as_slice() {
+                [] => false,
+                [flag] if flag == "--bless" => true,
+                _ => {
+                    println!("snapshot only supports --bless");
+                    return;
+                }
+            };
+            if paths.is_empty() {
+                println!("snapshot expects files or directories");
+                return;
+            }
+            if !run_snapshots(&paths, bless, &Options::default()) {
+                process::exit(1);
+            }
+            return;
+        }
+        _ => {}
+    }
+    let mut args = env::args().skip(1);
+    let mut file = None;
+    let mut options = Options::default();
+    let mut run_as_filter = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" => {
+                print_usage();
+                return;
+            }
+            "--width" => {
+                let value = args.next().and_then(|value| value.parse().ok());
+                if value.is_none() {
+                    println!("--width expects a positive number");
+                    return;
+                }
+                options.width = value.unwrap();
+            }
+            "--emit" => {
+                let value = args.next().and_then(|value| Emit::from_name(&value));
+                if value.is_none() {
+                    println!("--emit expects one of: text, dot, dot-trace, json, blc, blc8, ski");
+                    return;
+                }
+                options.emit = value.unwrap();
+            }
+            "--typecheck" => options.typecheck = true,
+            "--stats" => options.stats = true,
+            "--io" => run_as_filter = true,
+            "--no-cache" => options.cache = None,
+            option @ ("--allow" | "--warn") => {
+                match args.next().as_deref().and_then(Lint::from_name) {
+                    Some(lint) => {
+                        options.allowed.retain(|allowed| *allowed != lint);
+                        if option == "--allow" {
+                            options.allowed.push(lint);
+                        }
+                    }
+                    None => {
+                        let names: Vec<&str> = Lint::ALL.iter().map(|lint| lint.name()).collect();
+                        println!("{option} expects one of: {}", names.join(", "));
+                        return;
+                    }
+                }
+            }
+            "--backend" => match args.next().as_deref() {
+                Some("lambda") => options.ski_backend = false,
+                Some("ski") => options.ski_backend = true,
+                _ => {
+                    println!("--backend expects one of: lambda, ski");
+                    return;
+                }
+            },
+            "--combinators" => match args.next().as_deref() {
+                Some("ski") => options.basis = Basis::Ski,
+                Some("skibcw") => options.basis = Basis::Skibcw,
+                _ => {
+                    println!("--combinators expects one of: ski, skibcw");
+                    return;
+                }
+            },
+            _ => file = Some(arg),
+        }
+    }
+    if file.is_none() {
         print_usage();
         return;
     }
-    let arg = arg.unwrap();
-    if arg == "--help" {
-        print_usage();
+    if options.ski_backend && options.emit != Emit::Text {
+        println!("--backend ski only supports --emit text");
+        return;
+    }
+    if run_as_filter && (options.ski_backend || options.emit != Emit::Text) {
+        println!("--io only supports the lambda backend and --emit text");
         return;
     }
-    let file = arg;
-    let contents = fs::read_to_string(file);
+    let file = file.unwrap();
+    let contents = fs::read(&file);
     if contents.is_err() {
         println!("Error reading file. Please check your path and try again");
         return;
     }
     let contents = contents.unwrap();
-    let (result, bindings) = run_program(&contents);
-    println!("{}", format_lambda(&result, &bindings));
+    let diagnostics = &mut io::stderr();
+    if run_as_filter {
+        let (lambda, mut bindings) = match read_program(&file, contents, &options, diagnostics) {
+            Ok(program) => program,
+            Err(error) => {
+                println!("error: {error}");
+                return;
+            }
+        };
+        let (lambda, _) = optimize(lambda, &mut bindings);
+        let (lambda, _) =
+            normalize_top_level(lambda, &mut bindings, options.cache.as_deref(), diagnostics);
+        // on stderr, as stdout is the output of the program
+        if let Err(error) = run_filter(
+            lambda,
+            &mut bindings,
+            io::stdin().lock(),
+            &mut io::stdout().lock(),
+        ) {
+            eprintln!("{error}");
+        }
+        return;
+    }
+    match run(&file, contents, &options, diagnostics) {
+        Ok(output) if options.emit == Emit::Blc8 => {
+            io::stdout().write_all(&bits_to_bytes(&output)).unwrap()
+        }
+        Ok(output) => print!("{output}"),
+        Err(error) => {
+            println!("error: {error}");
+            process::exit(1);
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{helpers::format_lambda, run_program, Lambda};
+    use crate::{
+        blc::{blc_to_lambda, lambda_to_blc},
+        compiler::compile_with_assertions,
+        dot::lambda_to_dot,
+        helpers::{format_lambda, format_normal_form},
+        json::{json_to_lambda, lambda_to_json},
+        literals::decode_numeral,
+        parser::{parse_program, remove_whitespace},
+        reducer::full_reduce,
+        run_program,
+        ski::{format_combinators, lambda_to_combinators, reduce_combinators, Basis},
+        types::{format_type, infer_type},
+        Lambda, ITERATIONS,
+    };
 
     #[test]
     fn simple_reduction() {
@@ -329,6 +899,120 @@ mod tests {
         );
     }
 
+    #[test]
+    fn local_lets() {
+        let text = "
+        let succ n,f,x(
+            let step = n.f.x in f.step
+        );
+        let zero f,x(x);
+        succ.(let one = succ.zero in succ.one)
+        ";
+
+        let (result, bindings) = run_program(text);
+        assert_eq!(format_lambda(&result, &bindings), "f(x(f.(f.(f.(x)))))");
+    }
+
+    const ARITHMETIC: &str = "
+        let true t,f(t);
+        let false t,f(f);
+        let zero f,x(x);
+        let succ n,f,x(f.(n.f.x));
+        let pred n,f,x(n.(g,h(h.(g.f))).(u(x)).(u(u)));
+        let mul n,m,f(n.(m.f));
+        let is_zero n(n.(x(false)).true);
+    ";
+
+    #[test]
+    fn infix_operators() {
+        let text = format!(
+            "{ARITHMETIC}
+            infixl 6 + = add;
+            infixl 7 * = mul;
+            let add m,n,f,x((m.f).(n.f.x));
+            let one succ.zero;
+            let two one + one;
+            two * (one + two) + one
+            "
+        );
+
+        let (result, bindings) = run_program(&text);
+        assert_eq!(
+            format_lambda(&result, &bindings),
+            "f(x(f.(f.(f.(f.(f.(f.(f.(x)))))))))"
+        );
+    }
+
+    #[test]
+    fn data_types() {
+        let text = format!(
+            "{ARITHMETIC}
+            data List = Nil | Cons head tail;
+            data Option = None | Some value;
+            letrec length xs(
+                match xs {{
+                    Nil => zero;
+                    Cons _ tail => succ.(length.tail)
+                }}
+            );
+            let first xs(match xs {{ Cons head _ => Some.head; _ => None }});
+            let or_zero option(match option {{ None => zero; Some value => value }});
+            let list Cons.a.(Cons.b.(Cons.c.Nil));
+            or_zero.(first.(Cons.(length.list).Nil))
+            "
+        );
+
+        let (result, bindings) = run_program(&text);
+        assert_eq!(format_lambda(&result, &bindings), "f(x(f.(f.(f.(x)))))");
+    }
+
+    #[test]
+    fn string_literals() {
+        let text = r#"
+            let append xs,ys,c,n(xs.c.(ys.c.n));
+            let map f,xs,c,n(xs.(h,t(c.(f.h).t)).n);
+            let next n,f,x(f.(n.f.x));
+            append.(map.next."GDKKN").(append.(", \"").(c,n(c.'!'.n)))
+        "#;
+        let (result, _bindings) = run_program(text);
+        assert_eq!(
+            crate::literals::decode_string(&result).as_deref(),
+            Some("HELLO, \"!")
+        );
+    }
+
+    #[test]
+    fn factorial() {
+        let text = format!(
+            "{ARITHMETIC}
+            letrec fact n(
+                (is_zero.n).(succ.zero).(mul.n.(fact.(pred.n)))
+            );
+            fact.(succ.(succ.(succ.zero)))
+            "
+        );
+
+        let (result, bindings) = run_program(&text);
+        assert_eq!(
+            format_lambda(&result, &bindings),
+            "f(x(f.(f.(f.(f.(f.(f.(x))))))))"
+        );
+    }
+
+    #[test]
+    fn mutual_recursion() {
+        let text = format!(
+            "{ARITHMETIC}
+            letrec even n((is_zero.n).true.(odd.(pred.n)))
+            and odd n((is_zero.n).false.(even.(pred.n)));
+            even.(succ.(succ.(succ.zero)))
+            "
+        );
+
+        let (result, bindings) = run_program(&text);
+        assert_eq!(format_lambda(&result, &bindings), "t(f(f))");
+    }
+
     #[test]
     #[should_panic]
     fn omega() {
@@ -340,4 +1024,47 @@ mod tests {
 
         let (_result, _bindings) = run_program(text);
     }
+
+    #[test]
+    fn deep_numeral_through_every_output() {
+        let depth = 100_000;
+        let numeral = format!("f,x({}x{})", "f.(".repeat(depth), ")".repeat(depth));
+        let literal = format!("let c '\\u{{{depth:x}}}';\nc");
+        let [(lambda, bindings), _] = [numeral, literal].map(|source| {
+            let (compiled, _assertions) = compile_with_assertions(&source).unwrap();
+            let (lambda, mut bindings) = parse_program(&compiled).unwrap();
+            let lambda = full_reduce(lambda, &mut bindings, ITERATIONS);
+            assert_eq!(decode_numeral(&lambda), Some(depth as u32));
+            (lambda, bindings)
+        });
+        let text = format_lambda(&lambda, &bindings);
+        assert_eq!(parse_program(&text).unwrap().0, lambda);
+        let pretty = format_normal_form(&lambda, &bindings, 80);
+        // the indentation stops growing, so the text grows linearly with the depth
+        assert!(pretty.len() < 200 * depth);
+        assert_eq!(remove_whitespace(&pretty), text);
+        let json = lambda_to_json(&lambda, &bindings);
+        assert_eq!(json_to_lambda(&json).unwrap().0, lambda);
+        let blc = lambda_to_blc(&lambda);
+        assert_eq!(
+            decode_numeral(&blc_to_lambda(&blc).unwrap().0),
+            Some(depth as u32)
+        );
+        assert!(lambda_to_dot(&lambda, &bindings).contains("digraph"));
+        let t = infer_type(&lambda, &bindings).unwrap();
+        assert_eq!(format_type(&t), "(a -> a) -> a -> a");
+        let (applied, bindings) = parse_program(&format!("{text}.(g).(y)")).unwrap();
+        let expected = format!("{}g y{}", "g (".repeat(depth - 1), ")".repeat(depth - 1));
+        for basis in [Basis::Ski, Basis::Skibcw] {
+            let combinators = lambda_to_combinators(&applied, basis);
+            let (reduced, _steps) = reduce_combinators(&combinators, 10 * depth);
+            assert_eq!(format_combinators(&reduced, &bindings), expected);
+        }
+        let operators = format!(
+            "infixl 6 + = add;\nlet add m,n(m);\nlet one f(f);\n{}one{}",
+            "(one + ".repeat(depth),
+            ")".repeat(depth)
+        );
+        assert!(compile_with_assertions(&operators).is_ok());
+    }
 }
@@ -0,0 +1,728 @@
+//! Normal forms of closed top-level definitions, which are computed once and substituted
+//! instead of the definitions, so that their copies are not reduced again at every use.
+//!
+//! Top-level definitions are the applied definitions at the root of a program and in the
+//! bodies of those, which is where `let` puts them. Their normal forms are also kept in a
+//! cache file across runs, keyed by the canonical form of the definition, which does not
+//! depend on names. The file starts with a header naming the reducer and its step budget,
+//! a file with another header and entries that cannot be read are ignored. The file keeps
+//! the most recently used entries, up to `MAX_ENTRIES`, and leaves out long normal forms.
+
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::{
+    arena::Arena,
+    helpers::collect_free,
+    reducer::{reduce, try_full_reduce_visiting, REDUCER},
+    Lambda, Parts, ITERATIONS,
+};
+
+/// The first line of the cache file, normal forms of other reducers or step budgets are
+/// not used.
+fn header() -> String {
+    format!(
+        "blis {} normal forms, reducer {REDUCER}, {ITERATIONS} iterations",
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+/// `lambda` with de Bruijn indices instead of names, which is the same for alpha-equivalent
+/// terms, e.g. `d d c1/1 v0` for `f,x(f.x)`. A definition applied to its parameter is `a`,
+/// followed by the parameter and the body, free variables are `?`. Also returns the binders
+/// in the order they appear.
+fn canonical_form(lambda: &Lambda) -> (String, Vec<usize>) {
+    enum Work<'l> {
+        Visit(&'l Lambda),
+        Body(usize, &'l Lambda),
+        PopScope(usize),
+    }
+    let mut tokens = Vec::new();
+    let mut binders = Vec::new();
+    // the depths of the binders of every binding in scope, innermost last
+    let mut scopes: HashMap<usize, Vec<usize>> = HashMap::new();
+    let mut depth = 0;
+    let de_bruijn = |scopes: &HashMap<usize, Vec<usize>>, depth: usize, index: usize| {
+        scopes
+            .get(&index)
+            .and_then(|depths| depths.last())
+            .map_or("?".to_owned(), |binder| (depth - binder - 1).to_string())
+    };
+    let mut work = vec![Work::Visit(lambda)];
+    while let Some(item) = work.pop() {
+        match item {
+            Work::Visit(Lambda::Variable(value)) => {
+                tokens.push(format!("v{}", de_bruijn(&scopes, depth, *value)));
+            }
+            Work::Visit(Lambda::Definition {
+                name_index,
+                body,
+                parameter,
+            }) => {
+                binders.push(*name_index);
+                tokens.push(if parameter.is_some() { "a" } else { "d" }.to_owned());
+                work.push(Work::Body(*name_index, body));
+                work.extend(parameter.as_deref().map(Work::Visit));
+            }
+            Work::Visit(Lambda::Call {
+                name_index,
+                parameters,
+            }) => {
+                tokens.push(format!(
+                    "c{}/{}",
+                    de_bruijn(&scopes, depth, *name_index),
+                    parameters.len()
+                ));
+                work.extend(parameters.iter().rev().map(Work::Visit));
+            }
+            Work::Body(name_index, body) => {
+                scopes.entry(name_index).or_default().push(depth);
+                depth += 1;
+                work.push(Work::PopScope(name_index));
+                work.push(Work::Visit(body));
+            }
+            Work::PopScope(name_index) => {
+                depth -= 1;
+                scopes.get_mut(&name_index).unwrap().pop();
+            }
+        }
+    }
+    (tokens.join(" "), binders)
+}
+
+/// Reads the closed term in `canonical_form`, giving its binders new bindings with `names`,
+/// which are added to `bindings`. Returns `None` if it is not a closed term in that form.
+fn from_canonical_form(text: &str, names: &[String], bindings: &mut Vec<String>) -> Option<Lambda> {
+    enum Frame {
+        Definition {
+            binder: usize,
+            parameter: Option<Lambda>,
+            in_body: bool,
+        },
+        Call {
+            name_index: usize,
+            count: usize,
+            parameters: Vec<Lambda>,
+        },
+    }
+    let offset = bindings.len();
+    let mut frames = Vec::new();
+    // the bindings of the definitions around the next term, innermost last
+    let mut scope: Vec<usize> = Vec::new();
+    let mut binders = 0;
+    let mut tokens = text.split(' ');
+    let resolve = |scope: &[usize], index: &str| {
+        let index: usize = index.parse().ok()?;
+        scope
+            .len()
+            .checked_sub(index + 1)
+            .map(|position| scope[position])
+    };
+    let lambda = 'tokens: loop {
+        let token = tokens.next()?;
+        let mut term = match token.split_at_checked(1)? {
+            ("v", index) => Lambda::var(resolve(&scope, index)?),
+            ("c", rest) => {
+                let (index, count) = rest.split_once('/')?;
+                let name_index = resolve(&scope, index)?;
+                let count: usize = count.parse().ok()?;
+                if count > 0 {
+                    frames.push(Frame::Call {
+                        name_index,
+                        count,
+                        parameters: Vec::new(),
+                    });
+                    continue;
+                }
+                Lambda::call(name_index, Vec::new())
+            }
+            (kind @ ("d" | "a"), "") => {
+                let binder = offset + binders;
+                binders += 1;
+                let in_body = kind == "d";
+                if in_body {
+                    scope.push(binder);
+                }
+                frames.push(Frame::Definition {
+                    binder,
+                    parameter: None,
+                    in_body,
+                });
+                continue;
+            }
+            _ => return None,
+        };
+        // give the term to the frames it completes, until one needs more terms
+        loop {
+            match frames.last_mut() {
+                None => break 'tokens term,
+                Some(Frame::Definition {
+                    binder,
+                    parameter,
+                    in_body: in_body @ false,
+                }) => {
+                    *parameter = Some(term);
+                    *in_body = true;
+                    scope.push(*binder);
+                    continue 'tokens;
+                }
+                Some(Frame::Call {
+                    count, parameters, ..
+                }) if parameters.len() + 1 < *count => {
+                    parameters.push(term);
+                    continue 'tokens;
+                }
+                Some(_) => {}
+            }
+            term = match frames.pop().unwrap() {
+                Frame::Definition {
+                    binder, parameter, ..
+                } => {
+                    scope.pop();
+                    Lambda::def(binder, term, parameter)
+                }
+                Frame::Call {
+                    name_index,
+                    mut parameters,
+                    ..
+                } => {
+                    parameters.push(term);
+                    Lambda::call(name_index, parameters)
+                }
+            };
+        }
+    };
+    if tokens.next().is_some() || binders != names.len() {
+        return None;
+    }
+    bindings.extend(names.iter().cloned());
+    Some(lambda)
+}
+
+/// A normal form as it is stored in the cache file.
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    /// the names of the binders in the order they appear in `normal_form`
+    names: Vec<String>,
+    /// the canonical form of the normal form
+    normal_form: String,
+}
+
+impl Entry {
+    fn new(normal_form: &Lambda, bindings: &[String]) -> Self {
+        let (canonical, binders) = canonical_form(normal_form);
+        Entry {
+            names: binders
+                .into_iter()
+                .map(|binder| bindings[binder].clone())
+                .collect(),
+            normal_form: canonical,
+        }
+    }
+
+    /// The normal form with new bindings for its binders, which are added to `bindings`.
+    /// Returns `None` if the entry cannot be read.
+    fn to_lambda(&self, bindings: &mut Vec<String>) -> Option<Lambda> {
+        from_canonical_form(&self.normal_form, &self.names, bindings)
+    }
+}
+
+/// The most entries the cache file keeps, the least recently used ones are dropped.
+const MAX_ENTRIES: usize = 2000;
+
+/// The longest canonical form of a definition or normal form the cache file keeps, so that
+/// large numerals or strings do not fill it.
+const MAX_LENGTH: usize = 2000;
+
+/// An entry of the cache with the time it was last used.
+#[derive(Debug, Clone, PartialEq)]
+struct Cached {
+    entry: Option<Entry>,
+    /// larger for entries used later, the order of the cache file for loaded entries
+    used: u64,
+}
+
+/// The normal forms of definitions by the canonical form of the definition. Definitions
+/// without a normal form within `ITERATIONS` steps are remembered as `None`.
+pub(crate) struct Cache {
+    /// the file the cache is read from and written to, if it is persisted
+    path: Option<PathBuf>,
+    entries: HashMap<String, Cached>,
+    /// the `used` of the last used entry
+    clock: u64,
+    changed: bool,
+}
+
+impl Cache {
+    /// A cache that only lives as long as the program run.
+    #[cfg(test)]
+    pub(crate) fn in_memory() -> Self {
+        Cache {
+            path: None,
+            entries: HashMap::new(),
+            clock: 0,
+            changed: false,
+        }
+    }
+
+    /// Reads the cache file at `path`. A missing or unreadable file, or one written for
+    /// another reducer, is an empty cache, and lines that cannot be read are left out.
+    pub(crate) fn load(path: &Path) -> Self {
+        let mut entries = HashMap::new();
+        let contents = fs::read_to_string(path).unwrap_or_default();
+        let mut lines = contents.lines();
+        if lines.next() == Some(header().as_str()) {
+            // the most recently used entries come first
+            let lines: Vec<&str> = lines.take(MAX_ENTRIES).collect();
+            for (line, used) in lines.iter().zip((1..=lines.len() as u64).rev()) {
+                let (key, entry) = match line.split('\t').collect::<Vec<_>>()[..] {
+                    [key, "-"] => (key, None),
+                    [key, names, normal_form] => (
+                        key,
+                        Some(Entry {
+                            names: names.split(' ').map(str::to_owned).collect(),
+                            normal_form: normal_form.to_owned(),
+                        }),
+                    ),
+                    _ => continue,
+                };
+                entries
+                    .entry(key.to_owned())
+                    .or_insert(Cached { entry, used });
+            }
+        }
+        Cache {
+            path: Some(path.to_owned()),
+            clock: entries.len() as u64,
+            entries,
+            changed: false,
+        }
+    }
+
+    /// Writes the cache file if normal forms were added. The entries that other runs wrote
+    /// to the file since it was loaded are kept, and the file is replaced at once, so that
+    /// runs at the same time never read a partly written file.
+    pub(crate) fn save(&self) -> Result<(), String> {
+        let Some(path) = self.path.as_ref().filter(|_| self.changed) else {
+            return Ok(());
+        };
+        let written = Cache::load(path);
+        let mut entries: Vec<(&String, &Cached)> = written
+            .entries
+            .iter()
+            .filter(|(key, _)| !self.entries.contains_key(*key))
+            .chain(&self.entries)
+            .filter(|(key, cached)| {
+                key.len() <= MAX_LENGTH
+                    && cached
+                        .entry
+                        .as_ref()
+                        .is_none_or(|entry| entry.normal_form.len() <= MAX_LENGTH)
+            })
+            .collect();
+        entries.sort_by(|(a_key, a), (b_key, b)| b.used.cmp(&a.used).then(a_key.cmp(b_key)));
+        entries.truncate(MAX_ENTRIES);
+        let lines: String = entries
+            .into_iter()
+            .map(|(key, cached)| match &cached.entry {
+                Some(entry) => format!("{key}\t{}\t{}\n", entry.names.join(" "), entry.normal_form),
+                // no normal form
+                None => format!("{key}\t-\n"),
+            })
+            .collect();
+        let error = |error| format!("Error writing {}: {error}", path.display());
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory).map_err(error)?;
+        }
+        let temporary = path.with_extension(format!("{}.tmp", process::id()));
+        let contents = format!("{}\n{lines}", header());
+        fs::write(&temporary, contents)
+            .and_then(|_| fs::rename(&temporary, path))
+            .map_err(|cause| {
+                let _ = fs::remove_file(&temporary);
+                error(cause)
+            })
+    }
+
+    /// The normal form of the closed term `lambda`, reduced or taken from the cache.
+    fn normal_form(&mut self, lambda: &Lambda, bindings: &mut Vec<String>) -> Normalized {
+        let (key, _) = canonical_form(lambda);
+        self.clock += 1;
+        if let Some(cached) = self.entries.get_mut(&key) {
+            cached.used = self.clock;
+            match &cached.entry {
+                None => return Normalized::Cached(None),
+                // an entry that cannot be read is reduced again and replaced
+                Some(entry) => {
+                    if let Some(normal_form) = entry.to_lambda(bindings) {
+                        return Normalized::Cached(Some(normal_form));
+                    }
+                }
+            }
+        }
+        let mut arena = Arena::default();
+        let root = arena.insert(lambda);
+        let mut steps = 0;
+        let normal_form =
+            try_full_reduce_visiting(&mut arena, root, bindings, ITERATIONS, |_| steps += 1)
+                .map(|normal_form| arena.to_lambda(normal_form));
+        let entry = normal_form
+            .as_ref()
+            .map(|normal_form| Entry::new(normal_form, bindings));
+        let used = self.clock;
+        self.entries.insert(key, Cached { entry, used });
+        self.changed = true;
+        // the normal form itself is visited too
+        let steps = steps - usize::from(normal_form.is_some());
+        Normalized::Reduced(normal_form, steps)
+    }
+}
+
+/// A normal form taken from the cache, or reduced in the given number of steps. `None` if
+/// there is no normal form within `ITERATIONS` steps.
+enum Normalized {
+    Cached(Option<Lambda>),
+    Reduced(Option<Lambda>, usize),
+}
+
+/// The cache file in the user's cache directory.
+pub(crate) fn default_path() -> PathBuf {
+    let directory = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .unwrap_or_else(env::temp_dir);
+    directory.join("blis").join("normal-forms")
+}
+
+/// How normalizing the top-level definitions went.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Stats {
+    /// the definitions replaced by their normal forms
+    pub(crate) normalized: usize,
+    /// the normal forms taken from the cache
+    pub(crate) cached: usize,
+    /// the steps of reducing the definitions and of substituting them
+    pub(crate) steps: usize,
+}
+
+/// Substitutes the normal forms of the closed top-level definitions of `lambda` for them.
+/// Definitions that are not closed or have no normal form within `ITERATIONS` steps are
+/// kept. Also returns how many were replaced and the steps that took.
+pub(crate) fn normalize_definitions(
+    lambda: Lambda,
+    bindings: &mut Vec<String>,
+    cache: &mut Cache,
+) -> (Lambda, Stats) {
+    // reducing the outer definition can leave a new redex at the root, like in
+    // `x(x.x).(x(x.x))`, so only as many definitions as the program has are visited
+    let mut length = 0;
+    let mut link = &lambda;
+    while let Lambda::Definition {
+        body,
+        parameter: Some(_),
+        ..
+    } = link
+    {
+        length += 1;
+        link = body;
+    }
+    let mut kept = Vec::new();
+    let mut stats = Stats::default();
+    let mut current = lambda;
+    for _ in 0..length {
+        let Lambda::Definition {
+            parameter: Some(parameter),
+            ..
+        } = &current
+        else {
+            break;
+        };
+        let mut free = Vec::new();
+        collect_free(parameter, &mut Vec::new(), &mut free);
+        let normal_form = if free.is_empty() {
+            match cache.normal_form(parameter, bindings) {
+                Normalized::Cached(normal_form) => {
+                    stats.cached += usize::from(normal_form.is_some());
+                    normal_form
+                }
+                Normalized::Reduced(normal_form, steps) => {
+                    stats.steps += steps;
+                    normal_form
+                }
+            }
+        } else {
+            None
+        };
+        let Parts::Definition {
+            name_index,
+            body,
+            parameter: Some(parameter),
+        } = current.into_parts()
+        else {
+            unreachable!()
+        };
+        current = match normal_form {
+            Some(normal_form) => {
+                stats.normalized += 1;
+                stats.steps += 1;
+                reduce(Lambda::def(name_index, body, Some(normal_form)), bindings)
+            }
+            None => {
+                kept.push((name_index, parameter));
+                body
+            }
+        };
+    }
+    let lambda = kept
+        .into_iter()
+        .rev()
+        .fold(current, |body, (name_index, parameter)| {
+            Lambda::def(name_index, body, Some(parameter))
+        });
+    (lambda, stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs, path::PathBuf};
+
+    use crate::{
+        compiler::compile,
+        helpers::{alpha_equivalent, format_lambda},
+        parser::parse_program,
+        reducer::full_reduce,
+        Lambda, ITERATIONS,
+    };
+
+    use super::{
+        canonical_form, header, normalize_definitions, Cache, Cached, Entry, MAX_ENTRIES,
+        MAX_LENGTH,
+    };
+
+    fn program(text: &str) -> (Lambda, Vec<String>) {
+        parse_program(&compile(text).unwrap()).unwrap()
+    }
+
+    fn entries(cache: &Cache) -> HashMap<&String, &Option<Entry>> {
+        cache
+            .entries
+            .iter()
+            .map(|(key, cached)| (key, &cached.entry))
+            .collect()
+    }
+
+    fn temporary_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("blis-{name}-{}", std::process::id()))
+    }
+
+    const NUMERALS: &str = "
+        let succ n,f,x(f.(n.f.x));
+        let add m,n,f,x(m.f.(n.f.x));
+        let zero _f,x(x);
+        let two succ.(succ.zero);
+    ";
+
+    #[test]
+    fn canonical_form_does_not_depend_on_names() {
+        let (a, _) = program("f,x(f.(f.x))");
+        let (b, _) = program("g,y(g.(g.y))");
+        let (c, _) = program("f,x(f.(x.x))");
+        assert_eq!(canonical_form(&a), canonical_form(&b));
+        assert_eq!(canonical_form(&a).0, "d d c1/1 c1/1 v0");
+        assert_ne!(canonical_form(&a).0, canonical_form(&c).0);
+    }
+
+    #[test]
+    fn closed_definitions_are_normalized() {
+        let (lambda, mut bindings) = program(&format!("{NUMERALS} add.two.two"));
+        let mut expected_bindings = bindings.clone();
+        let expected = full_reduce(lambda.clone(), &mut expected_bindings, ITERATIONS);
+        let mut cache = Cache::in_memory();
+        let (normalized, stats) = normalize_definitions(lambda, &mut bindings, &mut cache);
+        // succ, add, zero and two
+        assert_eq!(stats.normalized, 4);
+        assert_eq!(stats.cached, 0);
+        assert!(stats.steps > 4);
+        let result = full_reduce(normalized, &mut bindings, ITERATIONS);
+        assert!(alpha_equivalent(
+            &expected,
+            &expected_bindings,
+            &result,
+            &bindings
+        ));
+    }
+
+    #[test]
+    fn open_and_diverging_definitions_are_kept() {
+        let (lambda, mut bindings) = program("let loop x(x.x).(x(x.x)); let y free; z(z)");
+        let mut cache = Cache::in_memory();
+        let (kept, stats) = normalize_definitions(lambda.clone(), &mut bindings, &mut cache);
+        assert_eq!(stats.normalized, 0);
+        assert_eq!(stats.steps, ITERATIONS);
+        assert_eq!(kept, lambda);
+        let entries: Vec<_> = cache.entries.values().map(|cached| &cached.entry).collect();
+        assert_eq!(entries, vec![&None]);
+    }
+
+    #[test]
+    fn redexes_left_at_the_root_are_not_followed() {
+        // substituting the normal form `x(x.x)` gives the same program again
+        let (lambda, mut bindings) = program("x(x.x).(x(x.x))");
+        let (result, stats) =
+            normalize_definitions(lambda.clone(), &mut bindings, &mut Cache::in_memory());
+        assert_eq!(stats.normalized, 1);
+        assert!(alpha_equivalent(&result, &bindings, &lambda, &bindings));
+    }
+
+    #[test]
+    fn entries_keep_binder_names() {
+        let (lambda, bindings) = program("a,a(a)");
+        let entry = Entry::new(&lambda, &bindings);
+        assert_eq!(entry.names, vec!["a", "a"]);
+        let mut other_bindings = vec!["x".to_string()];
+        let restored = entry.to_lambda(&mut other_bindings).unwrap();
+        assert_eq!(other_bindings, vec!["x", "a", "a"]);
+        assert_eq!(
+            restored,
+            Lambda::def(1, Lambda::def(2, Lambda::var(2), None), None)
+        );
+    }
+
+    #[test]
+    fn cache_file_round_trip() {
+        let path = std::env::temp_dir().join(format!("blis-cache-{}", std::process::id()));
+        let (lambda, bindings) = program(&format!("{NUMERALS} add.two.two"));
+        let run = |cache: &mut Cache| {
+            let mut bindings = bindings.clone();
+            let (lambda, _) = normalize_definitions(lambda.clone(), &mut bindings, cache);
+            let result = full_reduce(lambda, &mut bindings, ITERATIONS);
+            format_lambda(&result, &bindings)
+        };
+        let mut cache = Cache::load(&path);
+        assert!(cache.entries.is_empty());
+        let first = run(&mut cache);
+        cache.save().unwrap();
+
+        let mut loaded = Cache::load(&path);
+        assert_eq!(entries(&loaded), entries(&cache));
+        assert_eq!(run(&mut loaded), first);
+        assert!(!loaded.changed);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unreadable_entries_are_misses() {
+        let path = std::env::temp_dir().join(format!("blis-corrupt-{}", std::process::id()));
+        let (lambda, bindings) = program(&format!("{NUMERALS} add.two.two"));
+        let run = |cache: &mut Cache| {
+            let mut bindings = bindings.clone();
+            let (lambda, stats) = normalize_definitions(lambda.clone(), &mut bindings, cache);
+            let result = full_reduce(lambda, &mut bindings, ITERATIONS);
+            (format_lambda(&result, &bindings), stats.cached)
+        };
+        let mut cache = Cache::in_memory();
+        let (expected, _) = run(&mut cache);
+        let mut keys: Vec<&String> = cache.entries.keys().collect();
+        keys.sort();
+        // a truncated normal form, one with an unbound variable, a truncated line and a line
+        // of another definition, which is not looked up
+        let contents = format!(
+            "{}\n{}\tf x\td d c1/1 c1/\n{}\tf x\td d c1/1 c5/1 v0\n{}\tf\n{}\tx\td v0\n",
+            header(),
+            keys[0],
+            keys[1],
+            keys[2],
+            &keys[3][..keys[3].len() - 1],
+        );
+        fs::write(&path, contents).unwrap();
+        let mut loaded = Cache::load(&path);
+        assert_eq!(run(&mut loaded), (expected.clone(), 0));
+        assert!(loaded.changed);
+        loaded.save().unwrap();
+        assert_eq!(run(&mut Cache::load(&path)), (expected, 4));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn files_of_other_versions_are_ignored() {
+        let path = std::env::temp_dir().join(format!("blis-version-{}", std::process::id()));
+        let (lambda, mut bindings) = program("let id x(x); id");
+        let mut cache = Cache::load(&path);
+        normalize_definitions(lambda, &mut bindings, &mut cache);
+        cache.save().unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with(&format!("{}\n", header())));
+        fs::write(&path, contents.replacen("blis", "blis 0", 1)).unwrap();
+        assert!(Cache::load(&path).entries.is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn least_recently_used_entries_are_evicted() {
+        let path = temporary_path("evict");
+        let mut cache = Cache::load(&path);
+        for used in 0..=MAX_ENTRIES as u64 {
+            let cached = Cached { entry: None, used };
+            cache.entries.insert(format!("d v{used}"), cached);
+        }
+        cache.changed = true;
+        cache.save().unwrap();
+        let loaded = Cache::load(&path);
+        assert_eq!(loaded.entries.len(), MAX_ENTRIES);
+        assert!(!loaded.entries.contains_key("d v0"));
+        assert!(loaded.entries.contains_key(&format!("d v{MAX_ENTRIES}")));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn long_normal_forms_are_not_written() {
+        let path = temporary_path("long");
+        let mut cache = Cache::load(&path);
+        let long = Entry {
+            names: vec!["x".to_owned()],
+            normal_form: format!("d{}", " v0".repeat(MAX_LENGTH)),
+        };
+        cache.entries.insert(
+            "d v0".to_owned(),
+            Cached {
+                entry: Some(long),
+                used: 1,
+            },
+        );
+        cache.entries.insert(
+            "a v0 v0".to_owned(),
+            Cached {
+                entry: None,
+                used: 2,
+            },
+        );
+        cache.changed = true;
+        cache.save().unwrap();
+        let loaded = Cache::load(&path);
+        assert_eq!(
+            entries(&loaded),
+            HashMap::from([(&"a v0 v0".to_owned(), &None)])
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn runs_at_the_same_time_keep_each_others_entries() {
+        let path = temporary_path("merge");
+        let mut first = Cache::load(&path);
+        let mut second = Cache::load(&path);
+        let (lambda, mut bindings) = program("let id x(x); let k x,y(x); id.k");
+        normalize_definitions(lambda, &mut bindings.clone(), &mut first);
+        let (lambda, _) = program("let id x(x); id");
+        normalize_definitions(lambda, &mut bindings, &mut second);
+        first.save().unwrap();
+        second.save().unwrap();
+        let loaded = Cache::load(&path);
+        assert_eq!(entries(&loaded), entries(&first));
+        fs::remove_file(&path).unwrap();
+    }
+}
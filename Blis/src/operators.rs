@@ -0,0 +1,323 @@
+//! User declared infix operators, e.g. `infixl 6 + = add;`.
+//!
+//! As names may contain symbols, an operator has to be separated from its operands by
+//! whitespace or parentheses: `a + b` is `add.(a).(b)`, while `a+b` is a name.
+
+use std::ops::Range;
+
+use crate::helpers::block_ends;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Associativity {
+    Left,
+    Right,
+    None,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Operator {
+    symbol: String,
+    precedence: u8,
+    associativity: Associativity,
+    function: String,
+}
+
+/// Parses `infixl 6 + = add`, `infixr` and `infix` (not associative) declarations.
+/// Returns `None` if `statement` is not an operator declaration.
+pub(crate) fn parse_operator(statement: &str) -> Option<Result<Operator, String>> {
+    let tokens: Vec<&str> = statement.split_whitespace().collect();
+    let associativity = match tokens.first() {
+        Some(&"infixl") => Associativity::Left,
+        Some(&"infixr") => Associativity::Right,
+        Some(&"infix") => Associativity::None,
+        _ => return None,
+    };
+    let [_, precedence, symbol, "=", function] = tokens.as_slice() else {
+        return Some(Err(format!(
+            "expected `{} <precedence> <operator> = <function>`",
+            tokens[0]
+        )));
+    };
+    let Ok(precedence) = precedence.parse() else {
+        return Some(Err(format!(
+            "the precedence of `{symbol}` has to be a number from 0 to 255"
+        )));
+    };
+    if symbol.contains(['(', ')', '.', ',', ';', ':']) || *symbol == "=" {
+        return Some(Err(format!("`{symbol}` cannot be used as an operator")));
+    }
+    Some(Ok(Operator {
+        symbol: symbol.to_string(),
+        precedence,
+        associativity,
+        function: function.to_string(),
+    }))
+}
+
+/// Finds the longest operator at `index`, if it is separated from its surroundings.
+fn operator_at<'a>(text: &str, index: usize, operators: &'a [Operator]) -> Option<&'a Operator> {
+    let before = text[..index].chars().next_back();
+    if !before.is_none_or(|c| c.is_whitespace() || c == ')') {
+        return None;
+    }
+    operators
+        .iter()
+        .filter(|operator| {
+            let Some(after) = text[index..].strip_prefix(operator.symbol.as_str()) else {
+                return false;
+            };
+            after
+                .chars()
+                .next()
+                .is_none_or(|c| c.is_whitespace() || c == '(')
+        })
+        .max_by_key(|operator| operator.symbol.len())
+}
+
+/// Nests operands and the operators between them by precedence, the operators of the same
+/// precedence to the left if they are left associative, to the right if right associative.
+/// Returns the calls that open before each operand and how many close after it.
+fn nest<'o>(operators: &[&'o Operator]) -> Result<(Vec<String>, Vec<usize>), String> {
+    // the calls opening before an operand, the innermost first
+    let mut openings: Vec<Vec<&str>> = vec![Vec::new(); operators.len() + 1];
+    let mut closings = vec![0; operators.len() + 1];
+    // the first and last operand of the nested parts and the operators not nested yet
+    let mut operands = vec![(0, 0)];
+    let mut pending: Vec<&'o Operator> = Vec::new();
+    let mut combine = |operands: &mut Vec<(usize, usize)>, operator: &'o Operator| {
+        let (_, last) = operands.pop().unwrap();
+        let (first, _) = operands.pop().unwrap();
+        openings[first].push(&operator.function);
+        closings[last] += 1;
+        operands.push((first, last));
+    };
+    for (index, operator) in operators.iter().enumerate() {
+        while let Some(previous) = pending.last() {
+            if previous.precedence < operator.precedence {
+                break;
+            }
+            if previous.precedence == operator.precedence {
+                if previous.associativity != operator.associativity {
+                    return Err(format!(
+                        "cannot mix `{}` and `{}`, which have the same precedence but different associativity",
+                        previous.symbol, operator.symbol
+                    ));
+                }
+                match operator.associativity {
+                    Associativity::Left => {}
+                    Associativity::Right => break,
+                    Associativity::None => {
+                        return Err(format!(
+                            "`{}` is not associative, use parentheses",
+                            operator.symbol
+                        ));
+                    }
+                }
+            }
+            combine(&mut operands, pending.pop().unwrap());
+        }
+        pending.push(operator);
+        operands.push((index + 1, index + 1));
+    }
+    while let Some(operator) = pending.pop() {
+        combine(&mut operands, operator);
+    }
+    let openings = openings
+        .iter()
+        .map(|functions| {
+            functions
+                .iter()
+                .rev()
+                .map(|function| format!("{function}.("))
+                .collect()
+        })
+        .collect();
+    Ok((openings, closings))
+}
+
+/// Replaces the operators in `text` by calls of their functions.
+pub(crate) fn replace_operators(text: &str, operators: &[Operator]) -> Result<String, String> {
+    struct Block<'o> {
+        /// the range of the block in `text`, without its parentheses
+        start: usize,
+        end: usize,
+        /// the operators outside of nested blocks, with their position
+        found: Vec<(usize, &'o Operator)>,
+    }
+    if operators.is_empty() {
+        return Ok(text.to_owned());
+    }
+    // a single pass with a stack of the open blocks, which collects the replacements as ranges
+    // of `text`, so that nested blocks are neither visited recursively nor copied per level
+    let ends = block_ends(text);
+    let mut replacements: Vec<(Range<usize>, String)> = Vec::new();
+    let root = Block {
+        start: 0,
+        end: text.len(),
+        found: Vec::new(),
+    };
+    let mut blocks = vec![root];
+    let mut index = 0;
+    while let Some(block) = blocks.last_mut() {
+        if index == block.end {
+            let block = blocks.pop().unwrap();
+            if !block.found.is_empty() {
+                replace_block(
+                    text,
+                    &block.found,
+                    block.start..block.end,
+                    &mut replacements,
+                )?;
+            }
+            index += 1;
+            continue;
+        }
+        let level = &text[block.start..block.end];
+        if let Some(operator) = operator_at(level, index - block.start, operators) {
+            block.found.push((index, operator));
+            index += operator.symbol.len();
+            continue;
+        }
+        let c = text[index..].chars().next().unwrap();
+        if c == '(' {
+            let end = ends.get(&index).ok_or("Expect closing parenthesis")?;
+            blocks.push(Block {
+                start: index + 1,
+                end: *end,
+                found: Vec::new(),
+            });
+        }
+        index += c.len_utf8();
+    }
+    // the replacements of a block lie between its operands, outside of its nested blocks
+    replacements.sort_by_key(|(range, _)| range.start);
+    let mut string = String::new();
+    let mut copied = 0;
+    for (range, replacement) in replacements {
+        string += &text[copied..range.start];
+        string += &replacement;
+        copied = range.end;
+    }
+    string += &text[copied..];
+    Ok(string)
+}
+
+/// Collects the replacements of the operators `found` in `block` and of the whitespace
+/// around its operands by the calls of the operator functions.
+fn replace_block(
+    text: &str,
+    found: &[(usize, &Operator)],
+    block: Range<usize>,
+    replacements: &mut Vec<(Range<usize>, String)>,
+) -> Result<(), String> {
+    let mut operands = Vec::new();
+    let mut start = block.start;
+    for (index, operator) in found {
+        operands.push(trimmed(text, start..*index));
+        start = index + operator.symbol.len();
+    }
+    operands.push(trimmed(text, start..block.end));
+    if let Some(position) = operands.iter().position(|operand| operand.is_empty()) {
+        let operator = &found[position.min(found.len() - 1)].1.symbol;
+        return Err(format!("operator `{operator}` is missing an operand"));
+    }
+    let operators: Vec<&Operator> = found.iter().map(|(_, operator)| *operator).collect();
+    let (mut openings, closings) = nest(&operators)?;
+    replacements.push((
+        block.start..operands[0].start,
+        std::mem::take(&mut openings[0]),
+    ));
+    for (index, pair) in operands.windows(2).enumerate() {
+        let between = format!("{}).({}", ")".repeat(closings[index]), openings[index + 1]);
+        replacements.push((pair[0].end..pair[1].start, between));
+    }
+    let last = operands.len() - 1;
+    replacements.push((operands[last].end..block.end, ")".repeat(closings[last])));
+    Ok(())
+}
+
+/// Shrinks `range` of `text` to leave out the whitespace at both ends.
+fn trimmed(text: &str, range: Range<usize>) -> Range<usize> {
+    let part = &text[range.clone()];
+    let start = range.start + part.len() - part.trim_start().len();
+    let end = range.end - (part.len() - part.trim_end().len());
+    start..end.max(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_operator, replace_operators, Operator};
+
+    fn operators() -> Vec<Operator> {
+        [
+            "infixl 6 + = add",
+            "infixl 7 * = mul",
+            "infixr 8 ^ = pow",
+            "infix 4 == = eq",
+            "infixr 3 && = and",
+        ]
+        .iter()
+        .map(|statement| parse_operator(statement).unwrap().unwrap())
+        .collect()
+    }
+
+    fn replace(text: &str) -> Result<String, String> {
+        replace_operators(text, &operators())
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(
+            replace("a + b * c"),
+            Ok("add.(a).(mul.(b).(c))".to_string())
+        );
+        assert_eq!(
+            replace("a * b + c"),
+            Ok("add.(mul.(a).(b)).(c)".to_string())
+        );
+        assert_eq!(
+            replace("a && b ^ c ^ d + e * f == g"),
+            Ok("and.(a).(eq.(add.(pow.(b).(pow.(c).(d))).(mul.(e).(f))).(g))".to_string())
+        );
+    }
+
+    #[test]
+    fn associativity() {
+        assert_eq!(
+            replace("a + b + c"),
+            Ok("add.(add.(a).(b)).(c)".to_string())
+        );
+        assert_eq!(
+            replace("a ^ b ^ c"),
+            Ok("pow.(a).(pow.(b).(c))".to_string())
+        );
+        assert!(replace("a == b == c").is_err());
+        assert!(replace("a ^ b && c").is_ok());
+    }
+
+    #[test]
+    fn parentheses_and_applications() {
+        assert_eq!(
+            replace("(a + b) * succ.c"),
+            Ok("mul.((add.(a).(b))).(succ.c)".to_string())
+        );
+        assert_eq!(
+            replace("n,f(f.(n + n))"),
+            Ok("n,f(f.(add.(n).(n)))".to_string())
+        );
+    }
+
+    #[test]
+    fn operators_need_whitespace() {
+        assert_eq!(replace("a+b"), Ok("a+b".to_string()));
+        assert_eq!(replace("x == y"), Ok("eq.(x).(y)".to_string()));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(replace("a +").is_err());
+        assert!(parse_operator("infixl + = add").unwrap().is_err());
+        assert!(parse_operator("infixl 6 . = add").unwrap().is_err());
+        assert!(parse_operator("let x y").is_none());
+    }
+}
@@ -0,0 +1,182 @@
+//! Simplifications before reduction: definitions whose name is not used are dropped,
+//! definitions used exactly once are inlined. Both are reduction steps the reducer would
+//! take anyway, so the normal form does not change.
+
+use crate::{reducer::reduce, Lambda, Parts};
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Stats {
+    pub(crate) removed: usize,
+    pub(crate) inlined: usize,
+}
+
+fn count_uses(name: usize, lambda: &Lambda) -> usize {
+    let mut count = 0;
+    let mut stack = vec![lambda];
+    while let Some(lambda) = stack.pop() {
+        match lambda {
+            Lambda::Variable(value) => count += (*value == name) as usize,
+            Lambda::Definition {
+                body, parameter, ..
+            } => {
+                stack.push(body);
+                stack.extend(parameter.as_deref());
+            }
+            Lambda::Call {
+                name_index,
+                parameters,
+            } => {
+                count += (*name_index == name) as usize;
+                stack.extend(parameters);
+            }
+        }
+    }
+    count
+}
+
+/// The number of nodes of `lambda`.
+pub(crate) fn size(lambda: &Lambda) -> usize {
+    let mut size = 0;
+    let mut stack = vec![lambda];
+    while let Some(lambda) = stack.pop() {
+        size += 1;
+        match lambda {
+            Lambda::Variable(_) => {}
+            Lambda::Definition {
+                body, parameter, ..
+            } => {
+                stack.push(body);
+                stack.extend(parameter.as_deref());
+            }
+            Lambda::Call { parameters, .. } => stack.extend(parameters),
+        }
+    }
+    size
+}
+
+fn optimize_with(lambda: Lambda, bindings: &mut Vec<String>, stats: &mut Stats) -> Lambda {
+    enum Work {
+        Visit(Lambda),
+        Definition(usize, bool),
+        Call(usize, usize),
+    }
+    let mut work = vec![Work::Visit(lambda)];
+    let mut done = Vec::new();
+    while let Some(item) = work.pop() {
+        match item {
+            Work::Visit(lambda) => match lambda.into_parts() {
+                Parts::Variable(value) => done.push(Lambda::var(value)),
+                Parts::Definition {
+                    name_index,
+                    body,
+                    parameter,
+                } => {
+                    // the body first, then the parameter
+                    work.push(Work::Definition(name_index, parameter.is_some()));
+                    work.extend(parameter.map(Work::Visit));
+                    work.push(Work::Visit(body));
+                }
+                Parts::Call {
+                    name_index,
+                    parameters,
+                } => {
+                    work.push(Work::Call(name_index, parameters.len()));
+                    work.extend(parameters.into_iter().rev().map(Work::Visit));
+                }
+            },
+            Work::Definition(name_index, has_parameter) => {
+                let parameter = has_parameter.then(|| done.pop().unwrap());
+                let body = done.pop().unwrap();
+                let Some(parameter) = parameter else {
+                    done.push(Lambda::def(name_index, body, None));
+                    continue;
+                };
+                done.push(match count_uses(name_index, &body) {
+                    0 => {
+                        stats.removed += 1;
+                        body
+                    }
+                    1 => {
+                        stats.inlined += 1;
+                        reduce(Lambda::def(name_index, body, Some(parameter)), bindings)
+                    }
+                    _ => Lambda::def(name_index, body, Some(parameter)),
+                });
+            }
+            Work::Call(name_index, count) => {
+                let parameters = done.split_off(done.len() - count);
+                done.push(Lambda::call(name_index, parameters));
+            }
+        }
+    }
+    done.pop().unwrap()
+}
+
+/// Drops unused and inlines single use definitions. Renamed definitions are added to `bindings`.
+pub(crate) fn optimize(lambda: Lambda, bindings: &mut Vec<String>) -> (Lambda, Stats) {
+    let mut stats = Stats::default();
+    let lambda = optimize_with(lambda, bindings, &mut stats);
+    (lambda, stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        arena::Arena, compiler::compile, helpers::format_lambda, parser::parse_program,
+        reducer::full_reduce_visiting,
+    };
+
+    use super::{optimize, size, Stats};
+
+    fn steps(text: &str, optimized: bool) -> (String, usize) {
+        let (mut lambda, mut bindings) = parse_program(&compile(text).unwrap()).unwrap();
+        if optimized {
+            lambda = optimize(lambda, &mut bindings).0;
+        }
+        let mut arena = Arena::default();
+        let root = arena.insert(&lambda);
+        let mut steps = 0;
+        let result = full_reduce_visiting(&mut arena, root, &mut bindings, 10000, |_| steps += 1);
+        (format_lambda(&arena.to_lambda(result), &bindings), steps)
+    }
+
+    #[test]
+    fn unused_and_single_use_definitions() {
+        let text = "
+        let unused y(y);
+        let id x(x);
+        let twice f,x(f.(f.x));
+        twice.id.(twice.id.a)";
+        let (lambda, mut bindings) = parse_program(&compile(text).unwrap()).unwrap();
+        let before = size(&lambda);
+        let (lambda, stats) = optimize(lambda, &mut bindings);
+        assert_eq!(
+            stats,
+            Stats {
+                removed: 1,
+                inlined: 0
+            }
+        );
+        assert!(size(&lambda) < before);
+        assert_eq!(
+            format_lambda(&lambda, &bindings),
+            "id(twice(twice.(id).(twice.(id).(a))).(f(x(f.(f.(x)))))).(x(x))"
+        );
+    }
+
+    #[test]
+    fn fewer_steps_with_the_same_result() {
+        let text = "
+        let zero _f,x(x);
+        let succ n,f,x(f.(n.f.x));
+        let add m,n,f,x((m.f).(n.f.x));
+        let one succ.zero;
+        let two succ.one;
+        let unused add.two.two;
+        add.two.(succ.one)";
+        let (plain, plain_steps) = steps(text, false);
+        let (optimized, optimized_steps) = steps(text, true);
+        assert_eq!(plain, optimized);
+        assert!(optimized_steps < plain_steps);
+    }
+}
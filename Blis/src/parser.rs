@@ -1,6 +1,9 @@
-use std::collections::VecDeque;
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::Range,
+};
 
-use crate::{helpers::find_block_end, Lambda};
+use crate::Lambda;
 
 #[derive(Debug)]
 enum ParseType {
@@ -9,140 +12,336 @@ enum ParseType {
     Call,
 }
 
+/// The symbol table of the parser. Names are interned, and every name has its own stack of
+/// the bindings in scope, so that lookups do not depend on how many names are in scope.
+///
+/// A definition shadows the bindings of its name until its scope is popped. A name that is
+/// used without a definition becomes a free variable, which stays in scope for the rest of
+/// the program, below the definitions of the same name.
 pub(crate) struct Binder {
     pub(crate) global_bindings: Vec<String>,
-    pub(crate) bindings_stack: Vec<usize>,
+    /// for every binding, the offset of its binder in the source, if it was marked
+    spans: Vec<Option<usize>>,
+    symbols: HashMap<String, usize>,
+    /// for every symbol, the bindings of the definitions in scope, innermost last
+    scopes: Vec<Vec<usize>>,
+    /// for every symbol, the binding of the free variable with that name
+    free: Vec<Option<usize>>,
 }
 
 impl Binder {
     fn new() -> Self {
         Binder {
             global_bindings: Vec::new(),
-            bindings_stack: Vec::new(),
+            spans: Vec::new(),
+            symbols: HashMap::new(),
+            scopes: Vec::new(),
+            free: Vec::new(),
         }
     }
-    pub(crate) fn get_index(&mut self) -> usize {
-        self.global_bindings.len()
-    }
-    pub(crate) fn find_index(&self, value: &str) -> Option<usize> {
-        self.global_bindings
-            .iter()
-            .enumerate()
-            .filter(|(index, _)| self.bindings_stack.contains(index))
-            .find(|(_, binding)| *binding == value)
-            .map(|p| p.0)
-    }
-    pub(crate) fn new_binding(&mut self, name: String) -> usize {
-        let index = self.get_index();
-        self.bindings_stack.push(index);
-        self.global_bindings.push(name);
-        index
+
+    fn intern(&mut self, name: &str) -> usize {
+        if let Some(symbol) = self.symbols.get(name) {
+            return *symbol;
+        }
+        let symbol = self.scopes.len();
+        self.symbols.insert(name.to_owned(), symbol);
+        self.scopes.push(Vec::new());
+        self.free.push(None);
+        symbol
     }
-    fn pop_binding(&mut self) {
-        self.bindings_stack.pop();
+
+    fn new_binding(&mut self, name: &str, span: Option<usize>) -> usize {
+        self.global_bindings.push(name.to_owned());
+        self.spans.push(span);
+        self.global_bindings.len() - 1
     }
-}
 
-fn get_type(text: &str) -> ParseType {
-    let call_start = text.find('.');
-    let definition_start = text.find('(');
-    // otherwise it would be a value
-    if call_start.is_none() && definition_start.is_none() {
-        return ParseType::Value;
+    /// The innermost binding of `name`.
+    pub(crate) fn lookup(&self, name: &str) -> Option<usize> {
+        let symbol = *self.symbols.get(name)?;
+        self.scopes[symbol].last().copied().or(self.free[symbol])
     }
-    if definition_start.unwrap_or(usize::MAX) < call_start.unwrap_or(usize::MAX) {
-        return ParseType::Definition;
+
+    /// Opens the scope of a definition of `binder`, a name with an optional mark, returning
+    /// its binding.
+    fn push_scope(&mut self, binder: &str) -> usize {
+        let (name, span) = split_mark(binder);
+        let symbol = self.intern(name);
+        let index = self.new_binding(name, span);
+        self.scopes[symbol].push(index);
+        index
+    }
+
+    /// Closes the scope of the definition `index`, the innermost definition of its name.
+    fn pop_scope(&mut self, index: usize) {
+        let symbol = self.symbols[&self.global_bindings[index]];
+        let popped = self.scopes[symbol].pop();
+        debug_assert_eq!(popped, Some(index));
+    }
+
+    /// The binding of the free variable `name`, which is created on its first use.
+    pub(crate) fn free_variable(&mut self, name: &str) -> usize {
+        let symbol = self.intern(name);
+        if let Some(index) = self.free[symbol] {
+            return index;
+        }
+        let index = self.new_binding(name, None);
+        self.free[symbol] = Some(index);
+        index
     }
-    ParseType::Call
 }
 
-fn parse_call(text: &str, arguments: &mut VecDeque<Lambda>, binder: &mut Binder) -> Lambda {
-    let name_end = text.find('.').unwrap();
-    let name = &text[..name_end];
-    let mut args = parse_arguments(&text[name_end..], binder);
-    args.append(arguments);
-    arguments.append(&mut args);
-
-    Lambda::new_call(
-        name,
-        std::mem::take(arguments).into_iter().collect(),
-        binder,
-    )
+/// What to do with the arguments of a block, a definition or a call once they are parsed.
+enum Then {
+    Block {
+        inner: Range<usize>,
+        arguments: VecDeque<Lambda>,
+    },
+    Definition {
+        name: Range<usize>,
+        body: Range<usize>,
+        arguments: VecDeque<Lambda>,
+    },
+    Call {
+        name: Range<usize>,
+        arguments: VecDeque<Lambda>,
+    },
 }
 
-fn parse_arguments(mut text: &str, binder: &mut Binder) -> VecDeque<Lambda> {
-    let mut args = VecDeque::new();
+/// A part of the term that waits for the term parsed last.
+enum Frame {
+    /// the arguments in `rest` are still to be parsed
+    Arguments {
+        rest: Range<usize>,
+        parsed: VecDeque<Lambda>,
+        then: Then,
+    },
+    /// the body of a definition
+    Body {
+        name_index: usize,
+        parameter: Option<Lambda>,
+    },
+}
 
-    while !text.is_empty() {
-        assert!(text.starts_with('.'));
-        text = &text[1..];
+enum Step {
+    /// parse the text in the range, passing the arguments on to the innermost definitions
+    Parse(Range<usize>, VecDeque<Lambda>),
+    Done(Lambda),
+}
 
-        let call_end = text.find('.');
-        let block_start = text.find('(').unwrap_or(usize::MAX);
-        let arg_end = if block_start < call_end.unwrap_or(usize::MAX) {
-            find_block_end(text).unwrap() + 1
-        } else if call_end.is_some() {
-            call_end.unwrap()
-        } else {
-            text.len()
-        };
+/// Parentheses and dots are looked up in tables built once per program.
+struct Parser<'a> {
+    text: &'a str,
+    /// the position of the matching ')' for every '('
+    block_ends: Vec<usize>,
+    /// the position of the first '.' at or after every position, or the length of the text
+    next_dot: Vec<usize>,
+    /// the position of the first '(' at or after every position, or the length of the text
+    next_open: Vec<usize>,
+    frames: Vec<Frame>,
+    binder: Binder,
+}
 
-        let arg_text = &text[..arg_end];
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Result<Self, String> {
+        let bytes = text.as_bytes();
+        let mut block_ends = vec![usize::MAX; bytes.len()];
+        let mut open = Vec::new();
+        for (position, byte) in bytes.iter().enumerate() {
+            match byte {
+                b'(' => open.push(position),
+                b')' => {
+                    let start = open
+                        .pop()
+                        .ok_or("unbalanced parentheses: `)` without `(`")?;
+                    block_ends[start] = position;
+                }
+                _ => {}
+            }
+        }
+        if !open.is_empty() {
+            return Err("unbalanced parentheses: `(` without `)`".to_owned());
+        }
+        let mut next_dot = vec![bytes.len(); bytes.len() + 1];
+        let mut next_open = vec![bytes.len(); bytes.len() + 1];
+        for position in (0..bytes.len()).rev() {
+            next_dot[position] = if bytes[position] == b'.' {
+                position
+            } else {
+                next_dot[position + 1]
+            };
+            next_open[position] = if bytes[position] == b'(' {
+                position
+            } else {
+                next_open[position + 1]
+            };
+        }
+        Ok(Parser {
+            text,
+            block_ends,
+            next_dot,
+            next_open,
+            frames: Vec::new(),
+            binder: Binder::new(),
+        })
+    }
 
-        let arg = parse(arg_text, &mut VecDeque::new(), binder);
-        args.push_back(arg);
-        text = &text[arg_end..];
+    fn block_end(&self, start: usize) -> usize {
+        // every '(' has a matching ')', see `new`
+        self.block_ends[start]
     }
-    args
-}
 
-fn parse_definition(text: &str, arguments: &mut VecDeque<Lambda>, binder: &mut Binder) -> Lambda {
-    let name_end = text.find('(').unwrap();
-    let name = &text[..name_end];
-    assert!(
-        !binder
-            .bindings_stack
-            .iter()
-            .map(|index| binder.global_bindings[*index].as_str())
-            .collect::<Vec<&str>>()
-            .contains(&name),
-        "that name is already defined: {name}",
-    );
-
-    let body_end = find_block_end(text).unwrap();
-
-    let parameter = if body_end + 1 < text.len() {
-        let mut iter = parse_arguments(&text[body_end + 1..], binder).into_iter();
-        let argument = iter.next();
-        arguments.extend(iter);
-        argument
-    } else {
-        arguments.pop_front()
-    };
-
-    let name_index = binder.new_binding(name.to_owned());
-    let body = parse(&text[name_end + 1..body_end], arguments, binder);
-    binder.pop_binding();
-
-    Lambda::def(name_index, body, parameter)
-}
+    fn find(table: &[usize], range: &Range<usize>) -> Option<usize> {
+        Some(table[range.start]).filter(|position| *position < range.end)
+    }
 
-fn parse(text: &str, arguments: &mut VecDeque<Lambda>, binder: &mut Binder) -> Lambda {
-    if text.starts_with('(') {
-        let end = find_block_end(text).unwrap();
-        if end < text.len() - 1 {
-            assert!(text[end + 1..].starts_with('.'));
-            let mut args = parse_arguments(&text[end + 1..], binder);
-            arguments.append(&mut args);
+    fn get_type(&self, range: &Range<usize>) -> ParseType {
+        let call_start = Self::find(&self.next_dot, range);
+        let definition_start = Self::find(&self.next_open, range);
+        // otherwise it would be a value
+        if call_start.is_none() && definition_start.is_none() {
+            return ParseType::Value;
+        }
+        if definition_start.unwrap_or(usize::MAX) < call_start.unwrap_or(usize::MAX) {
+            return ParseType::Definition;
         }
-        return parse(&text[1..end], arguments, binder);
+        ParseType::Call
     }
-    let parse_type = get_type(text);
 
-    match parse_type {
-        ParseType::Value => Lambda::new_var(text, binder),
-        ParseType::Definition => parse_definition(text, arguments, binder),
-        ParseType::Call => parse_call(text, arguments, binder),
+    fn parse(&mut self, range: Range<usize>) -> Result<Lambda, String> {
+        let mut step = Step::Parse(range, VecDeque::new());
+        loop {
+            step = match step {
+                Step::Parse(range, arguments) => self.start(range, arguments)?,
+                Step::Done(lambda) => match self.frames.pop() {
+                    None => return Ok(lambda),
+                    Some(Frame::Arguments {
+                        rest,
+                        mut parsed,
+                        then,
+                    }) => {
+                        parsed.push_back(lambda);
+                        self.next_argument(rest, parsed, then)?
+                    }
+                    Some(Frame::Body {
+                        name_index,
+                        parameter,
+                    }) => {
+                        self.binder.pop_scope(name_index);
+                        Step::Done(Lambda::def(name_index, lambda, parameter))
+                    }
+                },
+            };
+        }
+    }
+
+    fn start(&mut self, range: Range<usize>, arguments: VecDeque<Lambda>) -> Result<Step, String> {
+        if self.text[range.clone()].starts_with('(') {
+            let end = self.block_end(range.start);
+            let then = Then::Block {
+                inner: range.start + 1..end,
+                arguments,
+            };
+            return self.next_argument(end + 1..range.end, VecDeque::new(), then);
+        }
+        match self.get_type(&range) {
+            ParseType::Value => Ok(Step::Done(Lambda::new_var(
+                &self.text[range],
+                &mut self.binder,
+            ))),
+            ParseType::Definition => {
+                let name_end = self.next_open[range.start];
+                let body_end = self.block_end(name_end);
+                let then = Then::Definition {
+                    name: range.start..name_end,
+                    body: name_end + 1..body_end,
+                    arguments,
+                };
+                self.next_argument(body_end + 1..range.end, VecDeque::new(), then)
+            }
+            ParseType::Call => {
+                let name_end = self.next_dot[range.start];
+                let then = Then::Call {
+                    name: range.start..name_end,
+                    arguments,
+                };
+                self.next_argument(name_end..range.end, VecDeque::new(), then)
+            }
+        }
+    }
+
+    /// Parses the next of the arguments in `rest`, each of which starts with a '.',
+    /// or continues with `then` if all of them are parsed.
+    fn next_argument(
+        &mut self,
+        rest: Range<usize>,
+        parsed: VecDeque<Lambda>,
+        then: Then,
+    ) -> Result<Step, String> {
+        if rest.is_empty() {
+            return self.finish(parsed, then);
+        }
+        if !self.text[rest.clone()].starts_with('.') {
+            let after = &self.text[rest.start - 1..rest.end];
+            return Err(format!("expected `.` before an argument in `{after}`"));
+        }
+        let start = rest.start + 1;
+        let call_end = Self::find(&self.next_dot, &(start..rest.end));
+        let block_start = Self::find(&self.next_open, &(start..rest.end)).unwrap_or(usize::MAX);
+        let arg_end = if block_start < call_end.unwrap_or(usize::MAX) {
+            self.block_end(block_start) + 1
+        } else {
+            call_end.unwrap_or(rest.end)
+        };
+        self.frames.push(Frame::Arguments {
+            rest: arg_end..rest.end,
+            parsed,
+            then,
+        });
+        Ok(Step::Parse(start..arg_end, VecDeque::new()))
+    }
+
+    fn finish(&mut self, mut parsed: VecDeque<Lambda>, then: Then) -> Result<Step, String> {
+        match then {
+            Then::Block {
+                inner,
+                mut arguments,
+            } => {
+                arguments.append(&mut parsed);
+                Ok(Step::Parse(inner, arguments))
+            }
+            Then::Definition {
+                name,
+                body,
+                mut arguments,
+            } => {
+                let parameter = if parsed.is_empty() {
+                    arguments.pop_front()
+                } else {
+                    let argument = parsed.pop_front();
+                    arguments.extend(parsed);
+                    argument
+                };
+                let name_index = self.binder.push_scope(&self.text[name]);
+                self.frames.push(Frame::Body {
+                    name_index,
+                    parameter,
+                });
+                Ok(Step::Parse(body, arguments))
+            }
+            Then::Call {
+                name,
+                mut arguments,
+            } => {
+                parsed.append(&mut arguments);
+                Ok(Step::Done(Lambda::new_call(
+                    &self.text[name],
+                    parsed.into(),
+                    &mut self.binder,
+                )?))
+            }
+        }
     }
 }
 
@@ -152,45 +351,89 @@ pub(crate) fn remove_whitespace(text: &str) -> String {
         .collect::<String>()
 }
 
-pub(crate) fn parse_program(text: &str) -> (Lambda, Vec<String>) {
-    let mut arguments = VecDeque::new();
-    let mut binder = Binder::new();
-    (
-        parse(&remove_whitespace(text), &mut arguments, &mut binder),
-        binder.global_bindings,
-    )
+/// Marks a binder in compiled text with the offset of its name in the source, as in
+/// `name\u{1}42`, so that warnings can point to it.
+const MARK: char = '\u{1}';
+
+/// `name` with the mark of the source offset `span`, if there is one.
+pub(crate) fn marked(name: &str, span: Option<usize>) -> String {
+    match span {
+        Some(span) => format!("{name}{MARK}{span}"),
+        None => name.to_owned(),
+    }
+}
+
+/// Splits a binder of compiled text into its name and the source offset of its mark.
+pub(crate) fn split_mark(binder: &str) -> (&str, Option<usize>) {
+    match binder.split_once(MARK) {
+        Some((name, span)) => (name, span.parse().ok()),
+        None => (binder, None),
+    }
+}
+
+/// `text` without the marks of its binders.
+pub(crate) fn strip_marks(text: &str) -> String {
+    let mut string = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != MARK {
+            string.push(c);
+            continue;
+        }
+        while chars.next_if(char::is_ascii_digit).is_some() {}
+    }
+    string
+}
+
+pub(crate) fn parse_program(text: &str) -> Result<(Lambda, Vec<String>), String> {
+    let (lambda, bindings, _) = parse_program_with_spans(text)?;
+    Ok((lambda, bindings))
+}
+
+/// A term, its bindings and the source offsets of the marked binders by binding.
+type Spanned = (Lambda, Vec<String>, Vec<Option<usize>>);
+
+/// Like `parse_program`, but also returns the source offsets of the marked binders by
+/// binding, see `marked`.
+pub(crate) fn parse_program_with_spans(text: &str) -> Result<Spanned, String> {
+    let text = remove_whitespace(text);
+    let mut parser = Parser::new(&text)?;
+    let lambda = parser.parse(0..text.len())?;
+    Ok((lambda, parser.binder.global_bindings, parser.binder.spans))
 }
 
 #[cfg(test)]
 mod tests {
 
-    use crate::{parser::parse_program, Lambda};
+    use crate::{literals::decode_numeral, parser::parse_program, Lambda};
+
+    use super::Binder;
 
     #[test]
     fn parse_value() {
         let text = "hi".to_string();
-        let (result, _bindings) = parse_program(&text);
+        let (result, _bindings) = parse_program(&text).unwrap();
         assert_eq!(result, Lambda::Variable(0))
     }
 
     #[test]
     fn parenthesis_around_value() {
         let text = "(hi)".to_string();
-        let (result, _bindings) = parse_program(&text);
+        let (result, _bindings) = parse_program(&text).unwrap();
         assert_eq!(result, Lambda::Variable(0))
     }
 
     #[test]
     fn function_definition() {
         let text = "a(a)".to_string();
-        let (result, bindings) = parse_program(&text);
+        let (result, bindings) = parse_program(&text).unwrap();
         assert_eq!(result, Lambda::def(0, Lambda::var(0), None), "{bindings:?}");
     }
 
     #[test]
     fn nested_function_def() {
         let text = "a(b(c(a)))".to_string();
-        let (result, _bindings) = parse_program(&text);
+        let (result, _bindings) = parse_program(&text).unwrap();
         assert_eq!(
             result,
             Lambda::def(
@@ -204,7 +447,7 @@ mod tests {
     #[test]
     fn immediate_call() {
         let text = "a(a).5".to_string();
-        let (result, bindings) = parse_program(&text);
+        let (result, bindings) = parse_program(&text).unwrap();
         assert_eq!(bindings, vec!["5", "a"]);
         assert_eq!(result, Lambda::def(1, Lambda::var(1), Some(Lambda::var(0))));
     }
@@ -212,7 +455,7 @@ mod tests {
     #[test]
     fn double_call() {
         let text = "a(b(a)).5.3".to_string();
-        let (result, bindings) = parse_program(&text);
+        let (result, bindings) = parse_program(&text).unwrap();
         assert_eq!(bindings, vec!["5", "3", "a", "b"]);
         assert_eq!(
             result,
@@ -235,7 +478,7 @@ mod tests {
         ).c(c).5
         "
         .to_string();
-        let (result, bindings) = parse_program(&text);
+        let (result, bindings) = parse_program(&text).unwrap();
         assert_eq!(bindings, vec!["c", "5", "a", "b"]);
         assert_eq!(
             result,
@@ -264,7 +507,7 @@ mod tests {
         ).d(e(e)).5.3
         "
         .to_string();
-        let (result, bindings) = parse_program(&text);
+        let (result, bindings) = parse_program(&text).unwrap();
         assert_eq!(bindings, vec!["d", "e", "5", "3", "a", "b", "c"]);
         assert_eq!(
             result,
@@ -298,7 +541,7 @@ mod tests {
         )).(d((e((e))))).((5)).3
         "
         .to_string();
-        let (result, bindings) = parse_program(&text);
+        let (result, bindings) = parse_program(&text).unwrap();
         assert_eq!(bindings, vec!["d", "e", "5", "3", "a", "b", "c"]);
         assert_eq!(
             result,
@@ -332,7 +575,7 @@ mod tests {
         ).5.3
         "
         .to_string();
-        let (result, bindings) = parse_program(&text);
+        let (result, bindings) = parse_program(&text).unwrap();
         assert_eq!(bindings, vec!["5", "3", "a", "b", "7", "c"]);
         assert_eq!(
             result,
@@ -351,7 +594,7 @@ mod tests {
     #[test]
     fn naming_duplication() {
         let text = "a(a.5).a(a)";
-        let (result, bindings) = parse_program(text);
+        let (result, bindings) = parse_program(text).unwrap();
         assert_eq!(bindings, vec!["a", "a", "5"]);
         assert_eq!(
             result,
@@ -366,7 +609,7 @@ mod tests {
     #[test]
     fn calling_with_itself() {
         let text = "a(a.a).a(a)";
-        let (result, bindings) = parse_program(text);
+        let (result, bindings) = parse_program(text).unwrap();
         assert_eq!(bindings, vec!["a", "a"]);
         assert_eq!(
             result,
@@ -379,9 +622,87 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
+    fn free_variable_in_body() {
+        let text = "f(f.(a(z)).(a(a))).g(g)";
+        let (result, bindings) = parse_program(text).unwrap();
+        assert_eq!(bindings, vec!["g", "f", "a", "z", "a"]);
+        assert_eq!(
+            result,
+            Lambda::def(
+                1,
+                Lambda::call(
+                    1,
+                    vec![
+                        Lambda::def(2, Lambda::var(3), None),
+                        Lambda::def(4, Lambda::var(4), None)
+                    ]
+                ),
+                Some(Lambda::def(0, Lambda::var(0), None))
+            )
+        )
+    }
+
+    #[test]
     fn naming_collision() {
         let text = "a(a(a))";
-        parse_program(text);
+        let (result, _bindings) = parse_program(text).unwrap();
+        assert_eq!(
+            result,
+            Lambda::def(0, Lambda::def(1, Lambda::var(1), None), None)
+        );
+    }
+
+    #[test]
+    fn deeply_nested_numeral() {
+        // far deeper than the native stack of a test thread allows for recursion
+        let text = format!("f(x({}x{}))", "f.(".repeat(100_000), ")".repeat(100_000));
+        let (result, bindings) = parse_program(&text).unwrap();
+        assert_eq!(bindings, vec!["f", "x"]);
+        assert_eq!(decode_numeral(&result), Some(100_000));
+        assert_eq!(result.clone(), result);
+    }
+
+    #[test]
+    fn definitions_shadow_free_variables() {
+        let mut binder = Binder::new();
+        assert_eq!(binder.lookup("x"), None);
+        let free = binder.free_variable("x");
+        let outer = binder.push_scope("x");
+        let inner = binder.push_scope("x");
+        assert_eq!(binder.lookup("x"), Some(inner));
+        binder.pop_scope(inner);
+        assert_eq!(binder.lookup("x"), Some(outer));
+        binder.pop_scope(outer);
+        assert_eq!(binder.lookup("x"), Some(free));
+        assert_eq!(binder.free_variable("x"), free);
+        assert_eq!(binder.global_bindings, vec!["x", "x", "x"]);
+    }
+
+    #[test]
+    fn many_names() {
+        let count = 20_000;
+        let binders: String = (0..count).map(|index| format!("d{index}(")).collect();
+        let text = format!("{binders}d0.(d{}){}", count - 1, ")".repeat(count));
+        let (result, bindings) = parse_program(&text).unwrap();
+        assert_eq!(bindings.len(), count);
+        let mut current = &result;
+        while let Lambda::Definition { body, .. } = current {
+            current = body;
+        }
+        assert_eq!(current, &Lambda::call(0, vec![Lambda::var(count - 1)]));
+    }
+
+    #[test]
+    fn invalid_programs_are_errors() {
+        assert_eq!(
+            parse_program("x(x))").map(|_| ()),
+            Err("unbalanced parentheses: `)` without `(`".to_owned())
+        );
+        assert!(parse_program("x(x").is_err());
+        assert!(parse_program("x(x)y").is_err());
+        assert_eq!(
+            parse_program("g.x").map(|_| ()),
+            Err("unknown function name `g`".to_owned())
+        );
     }
 }
@@ -0,0 +1,140 @@
+/// A document in the style of Wadler's "prettier printer".
+/// Groups are printed on one line if they fit into the remaining width,
+/// otherwise every `Break` directly inside them becomes a newline.
+#[derive(Debug, Clone)]
+pub(crate) enum Doc {
+    Text(String),
+    /// Nothing when flat, a newline followed by the current indentation when broken.
+    Break,
+    Nest(usize, Box<Doc>),
+    Concat(Vec<Doc>),
+    Group(Box<Doc>),
+}
+
+impl Doc {
+    pub(crate) fn text(text: impl Into<String>) -> Self {
+        Doc::Text(text.into())
+    }
+
+    pub(crate) fn nest(indent: usize, doc: Doc) -> Self {
+        Doc::Nest(indent, Box::new(doc))
+    }
+
+    pub(crate) fn group(doc: Doc) -> Self {
+        Doc::Group(Box::new(doc))
+    }
+}
+
+impl Drop for Doc {
+    fn drop(&mut self) {
+        let mut stack = Vec::new();
+        let detach = |doc: &mut Doc, stack: &mut Vec<Doc>| match doc {
+            Doc::Text(_) | Doc::Break => {}
+            Doc::Nest(_, doc) | Doc::Group(doc) => stack.push(std::mem::replace(doc, Doc::Break)),
+            Doc::Concat(docs) => stack.append(docs),
+        };
+        detach(self, &mut stack);
+        while let Some(mut doc) = stack.pop() {
+            detach(&mut doc, &mut stack);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Flat,
+    Broken,
+}
+
+/// Checks whether `doc` fits into `width` columns when printed flat.
+fn fits(doc: &Doc, mut width: usize) -> bool {
+    let mut stack = vec![doc];
+    while let Some(doc) = stack.pop() {
+        match doc {
+            Doc::Text(text) => match width.checked_sub(text.chars().count()) {
+                Some(remaining) => width = remaining,
+                None => return false,
+            },
+            Doc::Break => {}
+            Doc::Nest(_, doc) | Doc::Group(doc) => stack.push(doc),
+            Doc::Concat(docs) => stack.extend(docs.iter().rev()),
+        }
+    }
+    true
+}
+
+/// Renders `doc` into lines of at most `width` columns where possible. Indentation stops
+/// growing at the width, as the output of deeply nested documents would otherwise grow
+/// quadratically with their depth.
+pub(crate) fn render(doc: &Doc, width: usize) -> String {
+    let mut string = String::new();
+    let mut column = 0;
+    let mut stack = vec![(0, Mode::Broken, doc)];
+    while let Some((indent, mode, doc)) = stack.pop() {
+        match doc {
+            Doc::Text(text) => {
+                string += text;
+                column += text.chars().count();
+            }
+            Doc::Break => {
+                if mode == Mode::Broken {
+                    string.push('\n');
+                    string += &" ".repeat(indent);
+                    column = indent;
+                }
+            }
+            Doc::Nest(nested, doc) => stack.push(((indent + nested).min(width), mode, doc)),
+            Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|doc| (indent, mode, doc))),
+            Doc::Group(doc) => {
+                let mode = if mode == Mode::Flat || fits(doc, width.saturating_sub(column)) {
+                    Mode::Flat
+                } else {
+                    Mode::Broken
+                };
+                stack.push((indent, mode, doc));
+            }
+        }
+    }
+    string
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, Doc};
+
+    fn block(name: &str, body: Doc) -> Doc {
+        Doc::group(Doc::Concat(vec![
+            Doc::text(format!("{name}(")),
+            Doc::nest(2, Doc::Concat(vec![Doc::Break, body])),
+            Doc::Break,
+            Doc::text(")"),
+        ]))
+    }
+
+    #[test]
+    fn fitting_group_stays_flat() {
+        let doc = block("a", block("b", Doc::text("b")));
+        assert_eq!(render(&doc, 80), "a(b(b))");
+    }
+
+    #[test]
+    fn only_outer_group_breaks() {
+        let doc = block("abc", block("b", Doc::text("b")));
+        assert_eq!(render(&doc, 8), "abc(\n  b(b)\n)");
+    }
+
+    #[test]
+    fn nested_groups_break() {
+        let doc = block("a", block("b", Doc::text("c.(d)")));
+        assert_eq!(render(&doc, 4), "a(\n  b(\n    c.(d)\n  )\n)");
+    }
+
+    #[test]
+    fn indentation_stops_at_the_width() {
+        let doc = block("a", block("b", block("c", Doc::text("d.(e)"))));
+        assert_eq!(
+            render(&doc, 4),
+            "a(\n  b(\n    c(\n    d.(e)\n    )\n  )\n)"
+        );
+    }
+}
@@ -1,171 +1,427 @@
-use std::collections::VecDeque;
+//! Reduction in normal order: the leftmost outermost redex is contracted first, and an
+//! argument is substituted as it is, so an argument that is dropped is never reduced.
+//! Substitution renames a binder that would capture a free variable of the argument.
+//!
+//! The reduction works on terms in an `Arena`, so that a substituted argument is shared by
+//! all of its uses instead of copied, and `Lambda` terms are only converted at the boundary.
+//!
+//! Like all traversals of terms in this crate, from the parser to the emitters, it keeps its
+//! pending work on explicit stacks instead of recursing, so that deeply nested terms like
+//! large numerals do not overflow the native stack.
 
-use crate::Lambda;
+use std::collections::{HashMap, VecDeque};
 
-fn insert_arguments(root: &mut Lambda, args: &mut VecDeque<Lambda>) {
-    if args.is_empty() {
-        return;
-    }
-    match root {
-        Lambda::Variable(name) => {
-            let args = args.drain(..);
-            *root = Lambda::call(*name, args.collect());
-        }
-        Lambda::Definition {
-            name_index: _,
-            body,
-            parameter,
-        } => {
-            if parameter.is_none() {
-                *parameter = args.pop_front().map(Box::new);
+use crate::{
+    arena::{Arena, Node, TermId},
+    Lambda,
+};
+
+/// Identifies how the reducer picks redexes and renames binders, which decides the normal
+/// forms kept in the cache file, so it has to change whenever either of them does.
+pub(crate) const REDUCER: &str = "normal order 1";
+
+pub(crate) fn insert_arguments(mut root: &mut Lambda, args: &mut VecDeque<Lambda>) {
+    while !args.is_empty() {
+        match root {
+            Lambda::Variable(name) => {
+                let args = args.drain(..);
+                *root = Lambda::call(*name, args.collect());
+            }
+            Lambda::Definition {
+                name_index: _,
+                body,
+                parameter,
+            } => {
+                if parameter.is_none() {
+                    *parameter = args.pop_front().map(Box::new);
+                }
+                root = body;
+            }
+            Lambda::Call {
+                name_index: _,
+                parameters: parameter,
+            } => {
+                parameter.append(args);
             }
-            insert_arguments(body, args);
-        }
-        Lambda::Call {
-            name_index: _,
-            parameters: parameter,
-        } => {
-            parameter.append(args);
         }
     }
 }
 
-fn replace(name: usize, replacement: &Lambda, body: &Lambda) -> Lambda {
-    match body {
-        Lambda::Variable(value) => {
-            if value == &name {
-                replacement.clone()
-            } else {
-                Lambda::var(*value)
-            }
+/// Gives the definition `name_index` with `body` a new binding with the same name.
+fn rename(
+    arena: &mut Arena,
+    name_index: usize,
+    body: TermId,
+    bindings: &mut Vec<String>,
+) -> (usize, TermId) {
+    let renamed = bindings.len();
+    bindings.push(bindings[name_index].clone());
+    let variable = arena.var(renamed);
+    (
+        renamed,
+        replace(arena, name_index, variable, body, bindings),
+    )
+}
+
+/// Applies `root` to `args` by giving its definitions their missing parameters, like
+/// `insert_arguments`, but arguments that are moved into the body of a definition are not
+/// captured by it.
+fn apply(
+    arena: &mut Arena,
+    mut root: TermId,
+    mut args: VecDeque<TermId>,
+    bindings: &mut Vec<String>,
+) -> TermId {
+    // the definitions around the result, outermost first
+    let mut definitions = Vec::new();
+    let applied = loop {
+        if args.is_empty() {
+            break root;
         }
-        Lambda::Definition {
-            name_index,
-            body,
-            parameter,
-        } => {
-            if name == *name_index {
-                return body.as_ref().clone();
+        match arena.node(root) {
+            Node::Variable(value) => {
+                let value = *value;
+                break arena.call(value, args.into());
             }
-            let new_body = replace(name, replacement, body.as_ref());
-
-            let new_parameter = parameter
-                .as_ref()
-                .map(|p| replace(name, replacement, p.as_ref()))
-                .map(Box::new);
-
-            Lambda::Definition {
-                name_index: *name_index,
-                body: Box::new(new_body),
-                parameter: new_parameter,
+            Node::Call {
+                name_index,
+                parameters,
+            } => {
+                let (name_index, mut parameters) = (*name_index, parameters.clone());
+                parameters.extend(args);
+                break arena.call(name_index, parameters);
+            }
+            Node::Definition {
+                name_index,
+                body,
+                parameter,
+            } => {
+                let (mut name_index, mut body) = (*name_index, *body);
+                let parameter = parameter.or_else(|| args.pop_front());
+                if args.iter().any(|arg| arena.is_free(*arg, name_index)) {
+                    (name_index, body) = rename(arena, name_index, body, bindings);
+                }
+                definitions.push((name_index, parameter));
+                root = body;
             }
         }
-        Lambda::Call {
-            name_index,
-            parameters,
-        } => {
-            let mut new_parameter: VecDeque<Lambda> = parameters
-                .iter()
-                .map(|p| replace(name, replacement, p))
-                .collect();
-
-            if *name_index == name {
-                let mut replacement = replacement.clone();
-                insert_arguments(&mut replacement, &mut new_parameter);
-                replacement
-            } else {
-                Lambda::call(*name_index, new_parameter.into_iter().collect())
+    };
+    definitions
+        .into_iter()
+        .rev()
+        .fold(applied, |body, (name_index, parameter)| {
+            arena.def(name_index, body, parameter)
+        })
+}
+
+/// Substitutes `replacement` for `name` in `body`. Definitions that would capture one of the
+/// free variables of `replacement` get a new binding. Every distinct subterm is visited once
+/// and subterms in which `name` is not free are kept.
+fn replace(
+    arena: &mut Arena,
+    name: usize,
+    replacement: TermId,
+    body: TermId,
+    bindings: &mut Vec<String>,
+) -> TermId {
+    enum Work {
+        Visit(TermId),
+        /// the body of a definition, visited after its parameter
+        Body(TermId),
+        Definition(TermId, usize),
+        Call(TermId),
+    }
+    let mut replaced: HashMap<TermId, TermId> = HashMap::new();
+    let mut work = vec![Work::Visit(body)];
+    let mut done = Vec::new();
+    while let Some(item) = work.pop() {
+        match item {
+            Work::Visit(id) => {
+                if !arena.is_free(id, name) {
+                    done.push(id);
+                    continue;
+                }
+                if let Some(result) = replaced.get(&id) {
+                    done.push(*result);
+                    continue;
+                }
+                match arena.node(id) {
+                    // the variable is `name`, as it is free
+                    Node::Variable(_) => done.push(replacement),
+                    Node::Definition { parameter, .. } => {
+                        work.push(Work::Body(id));
+                        work.extend(parameter.map(Work::Visit));
+                    }
+                    Node::Call { parameters, .. } => {
+                        work.push(Work::Call(id));
+                        work.extend(parameters.iter().rev().copied().map(Work::Visit));
+                    }
+                }
+            }
+            Work::Body(id) => {
+                let Node::Definition {
+                    name_index, body, ..
+                } = *arena.node(id)
+                else {
+                    unreachable!()
+                };
+                // a copy of the definition shadows `name`, only its parameter can refer to it
+                if name == name_index || !arena.is_free(body, name) {
+                    work.push(Work::Definition(id, name_index));
+                    done.push(body);
+                    continue;
+                }
+                let (name_index, body) = if arena.is_free(replacement, name_index) {
+                    rename(arena, name_index, body, bindings)
+                } else {
+                    (name_index, body)
+                };
+                work.push(Work::Definition(id, name_index));
+                work.push(Work::Visit(body));
+            }
+            Work::Definition(id, name_index) => {
+                let has_parameter = matches!(
+                    arena.node(id),
+                    Node::Definition {
+                        parameter: Some(_),
+                        ..
+                    }
+                );
+                let body = done.pop().unwrap();
+                let parameter = has_parameter.then(|| done.pop().unwrap());
+                let result = arena.def(name_index, body, parameter);
+                replaced.insert(id, result);
+                done.push(result);
+            }
+            Work::Call(id) => {
+                let Node::Call {
+                    name_index,
+                    parameters,
+                } = arena.node(id)
+                else {
+                    unreachable!()
+                };
+                let name_index = *name_index;
+                let parameters = done.split_off(done.len() - parameters.len());
+                let result = if name_index == name {
+                    apply(arena, replacement, parameters.into(), bindings)
+                } else {
+                    arena.call(name_index, parameters)
+                };
+                replaced.insert(id, result);
+                done.push(result);
             }
         }
     }
+    done.pop().unwrap()
 }
 
-fn reduce(root: Lambda) -> Lambda {
-    if let Lambda::Definition {
+/// Contracts the redex `root`, a definition applied to its parameter.
+pub(crate) fn contract(arena: &mut Arena, root: TermId, bindings: &mut Vec<String>) -> TermId {
+    let Node::Definition {
         name_index,
         body,
-        parameter,
-    } = root
-    {
-        assert!(parameter.is_some());
-        let parameter = parameter.unwrap();
-        let parameter = Box::new(full_reduce(*parameter, 10));
-
-        return replace(name_index, &parameter, &body);
-    }
-    unreachable!()
+        parameter: Some(parameter),
+    } = *arena.node(root)
+    else {
+        unreachable!()
+    };
+    replace(arena, name_index, parameter, body, bindings)
 }
 
-fn find_reducible(root: Lambda) -> Result<Lambda, Lambda> {
-    match root {
-        Lambda::Variable(_) => Err(root),
-        Lambda::Definition {
-            name_index,
-            body,
-            parameter,
-        } => {
-            let new_body = find_reducible(*body.clone());
-            if let Ok(new_body) = new_body {
-                Ok(Lambda::def(name_index, new_body, parameter.map(|p| *p)))
-            } else if parameter.is_some() {
-                Ok(reduce(Lambda::def(
-                    name_index,
-                    *body,
-                    parameter.map(|p| *p),
-                )))
-            } else {
-                Err(Lambda::def(
+/// Like `contract`, for a redex that is not stored in an arena.
+pub(crate) fn reduce(root: Lambda, bindings: &mut Vec<String>) -> Lambda {
+    let mut arena = Arena::default();
+    let root = arena.insert(&root);
+    let reduced = contract(&mut arena, root, bindings);
+    arena.to_lambda(reduced)
+}
+
+/// Contracts the leftmost outermost redex of `root`, or returns `root` as the error if it
+/// is in normal form.
+pub(crate) fn find_reducible(
+    arena: &mut Arena,
+    root: TermId,
+    bindings: &mut Vec<String>,
+) -> Result<TermId, TermId> {
+    reduce_nth(arena, root, &mut 0, bindings)
+}
+
+/// Contracts the redex at position `index` in leftmost outermost order, or returns `root`
+/// as the error if there are not that many redexes. `index` is decreased by every redex
+/// that is skipped.
+pub(crate) fn reduce_nth(
+    arena: &mut Arena,
+    root: TermId,
+    index: &mut usize,
+    bindings: &mut Vec<String>,
+) -> Result<TermId, TermId> {
+    /// A term around the one searched, rebuilt from it once the redex is contracted.
+    enum Frame {
+        Body {
+            name_index: usize,
+            parameter: Option<TermId>,
+        },
+        Parameter {
+            name_index: usize,
+            body: TermId,
+        },
+        Argument {
+            name_index: usize,
+            parameters: Vec<TermId>,
+            position: usize,
+        },
+    }
+    let count = arena.redexes(root);
+    if *index >= count {
+        *index -= count;
+        return Err(root);
+    }
+    // the counts of redexes lead the way, terms before the redex are skipped as a whole
+    let mut frames = Vec::new();
+    let mut current = root;
+    let contracted = loop {
+        match arena.node(current).clone() {
+            Node::Definition {
+                name_index,
+                body,
+                parameter,
+            } => {
+                // normal order: the outermost redex first, so arguments that are not used
+                // (like the recursive case of a fixpoint) are never reduced
+                if let Some(parameter) = parameter {
+                    if *index == 0 {
+                        break contract(arena, current, bindings);
+                    }
+                    *index -= 1;
+                    let in_body = arena.redexes(body);
+                    if *index >= in_body {
+                        *index -= in_body;
+                        frames.push(Frame::Parameter { name_index, body });
+                        current = parameter;
+                        continue;
+                    }
+                }
+                frames.push(Frame::Body {
                     name_index,
-                    new_body.unwrap_err(),
-                    parameter.map(|p| *p),
-                ))
+                    parameter,
+                });
+                current = body;
             }
-        }
-        Lambda::Call {
-            name_index: function_name,
-            parameters,
-        } => {
-            let iter = parameters.into_iter().map(find_reducible);
-            let mut new_parameters = Vec::new();
-            let mut any_reduced = false;
-            for p in iter {
-                if let Ok(result) = p {
-                    any_reduced = true;
-                    new_parameters.push(result);
-                } else {
-                    new_parameters.push(p.unwrap_err());
+            Node::Call {
+                name_index,
+                parameters,
+            } => {
+                let mut position = 0;
+                while *index >= arena.redexes(parameters[position]) {
+                    *index -= arena.redexes(parameters[position]);
+                    position += 1;
                 }
+                current = parameters[position];
+                frames.push(Frame::Argument {
+                    name_index,
+                    parameters,
+                    position,
+                });
             }
-            if any_reduced {
-                Ok(Lambda::call(function_name, new_parameters))
-            } else {
-                Err(Lambda::call(function_name, new_parameters))
-            }
+            Node::Variable(_) => unreachable!("a term without redexes was searched"),
         }
-    }
+    };
+    Ok(frames
+        .into_iter()
+        .rev()
+        .fold(contracted, |term, frame| match frame {
+            Frame::Body {
+                name_index,
+                parameter,
+            } => arena.def(name_index, term, parameter),
+            Frame::Parameter { name_index, body } => arena.def(name_index, body, Some(term)),
+            Frame::Argument {
+                name_index,
+                mut parameters,
+                position,
+            } => {
+                parameters[position] = term;
+                arena.call(name_index, parameters)
+            }
+        }))
 }
 
-pub(crate) fn full_reduce(mut root: Lambda, iterations: usize) -> Lambda {
+/// Reduces the term `root` of `arena` to normal form, calling `visit` with every intermediate
+/// term, starting with `root` and ending with the normal form. Returns `None` if there is no
+/// normal form within `iterations` steps.
+pub(crate) fn try_full_reduce_visiting(
+    arena: &mut Arena,
+    mut root: TermId,
+    bindings: &mut Vec<String>,
+    iterations: usize,
+    mut visit: impl FnMut(TermId),
+) -> Option<TermId> {
     for _ in 0..iterations {
-        let result = find_reducible(root);
-        if let Err(result) = result {
-            return result;
+        visit(root);
+        match find_reducible(arena, root, bindings) {
+            Ok(reduced) => root = reduced,
+            Err(normal_form) => return Some(normal_form),
         }
-        root = result.unwrap();
     }
-    panic!("Term was not reducible in {iterations} iterations");
+    None
+}
+
+/// Like `try_full_reduce_visiting`, but panics if there is no normal form.
+#[cfg(test)]
+pub(crate) fn full_reduce_visiting(
+    arena: &mut Arena,
+    root: TermId,
+    bindings: &mut Vec<String>,
+    iterations: usize,
+    visit: impl FnMut(TermId),
+) -> TermId {
+    try_full_reduce_visiting(arena, root, bindings, iterations, visit)
+        .unwrap_or_else(|| panic!("Term was not reducible in {iterations} iterations"))
+}
+
+/// Like `try_full_reduce`, but panics if there is no normal form.
+#[cfg(test)]
+pub(crate) fn full_reduce(root: Lambda, bindings: &mut Vec<String>, iterations: usize) -> Lambda {
+    try_full_reduce(root, bindings, iterations)
+        .unwrap_or_else(|| panic!("Term was not reducible in {iterations} iterations"))
+}
+
+/// Reduces `root` to normal form, or returns `None` if it has none within `iterations`
+/// steps. Renamed definitions are added to `bindings`.
+pub(crate) fn try_full_reduce(
+    root: Lambda,
+    bindings: &mut Vec<String>,
+    iterations: usize,
+) -> Option<Lambda> {
+    let mut arena = Arena::default();
+    let root = arena.insert(&root);
+    try_full_reduce_visiting(&mut arena, root, bindings, iterations, |_| {})
+        .map(|normal_form| arena.to_lambda(normal_form))
 }
 
 #[cfg(test)]
 mod tests {
 
-    use crate::{reducer::full_reduce, Lambda};
+    use crate::{
+        arena::Arena,
+        generate::{Generator, Rng},
+        helpers::alpha_equivalent,
+        literals::decode_numeral,
+        parser::parse_program,
+        reducer::{find_reducible, full_reduce, reduce_nth},
+        Lambda,
+    };
+
+    fn bindings(count: usize) -> Vec<String> {
+        (0..count).map(|index| index.to_string()).collect()
+    }
 
     #[test]
     fn no_reduction() {
         // a(a)
         let lambda = Lambda::def(0, Lambda::var(0), None);
-        let reduced = full_reduce(lambda.clone(), 50);
+        let reduced = full_reduce(lambda.clone(), &mut bindings(8), 50);
         assert_eq!(lambda, reduced);
     }
 
@@ -176,7 +432,7 @@ mod tests {
             Lambda::call(0, vec![Lambda::var(1)]),
             Some(Lambda::def(2, Lambda::var(2), None)),
         );
-        let reduced = full_reduce(lambda, 50);
+        let reduced = full_reduce(lambda, &mut bindings(8), 50);
         assert_eq!(reduced, Lambda::var(1))
     }
 
@@ -196,7 +452,7 @@ mod tests {
                 Some(Lambda::var(5)),
             )),
         );
-        let reduced = full_reduce(lambda, 50);
+        let reduced = full_reduce(lambda, &mut bindings(8), 50);
         assert_eq!(reduced, Lambda::var(2));
     }
 
@@ -208,7 +464,7 @@ mod tests {
             Lambda::def(2, Lambda::call(0, vec![Lambda::var(2)]), None),
             Some(Lambda::def(1, Lambda::var(1), None)),
         );
-        let reduced = full_reduce(lambda, 50);
+        let reduced = full_reduce(lambda, &mut bindings(8), 50);
         assert_eq!(reduced, Lambda::def(2, Lambda::var(2), None))
     }
 
@@ -231,10 +487,44 @@ mod tests {
             ),
             Some(Lambda::def(4, Lambda::var(4), None)),
         );
-        let reduced = full_reduce(lambda, 50);
+        let reduced = full_reduce(lambda, &mut bindings(8), 50);
         assert_eq!(reduced, Lambda::var(3))
     }
 
+    #[test]
+    fn free_variable_is_not_captured() {
+        // 0(1(0.1)).(2(1.2)), where 1 is also free in the parameter => 8(1.8)
+        let lambda = Lambda::def(
+            0,
+            Lambda::def(1, Lambda::call(0, vec![Lambda::var(1)]), None),
+            Some(Lambda::def(2, Lambda::call(1, vec![Lambda::var(2)]), None)),
+        );
+        let mut bindings = bindings(8);
+        let reduced = full_reduce(lambda, &mut bindings, 50);
+        assert_eq!(
+            reduced,
+            Lambda::def(8, Lambda::call(1, vec![Lambda::var(8)]), None)
+        );
+        assert_eq!(bindings[8], "1");
+    }
+
+    #[test]
+    fn unused_argument_is_not_reduced() {
+        // 2(2.(5, 3(3.3).(4(4.4)))).(0(1(0))) => 5, the argument without normal form is dropped
+        let omega = Lambda::def(
+            3,
+            Lambda::call(3, vec![Lambda::var(3)]),
+            Some(Lambda::def(4, Lambda::call(4, vec![Lambda::var(4)]), None)),
+        );
+        let lambda = Lambda::def(
+            2,
+            Lambda::call(2, vec![Lambda::var(5), omega]),
+            Some(Lambda::def(0, Lambda::def(1, Lambda::var(0), None), None)),
+        );
+        let reduced = full_reduce(lambda, &mut bindings(8), 50);
+        assert_eq!(reduced, Lambda::var(5));
+    }
+
     #[test]
     fn calling_with_itself() {
         let lambda = Lambda::def(
@@ -242,10 +532,124 @@ mod tests {
             Lambda::call(0, vec![Lambda::var(0)]),
             Some(Lambda::def(1, Lambda::var(1), None)),
         );
-        let reduced = full_reduce(lambda, 50);
+        let reduced = full_reduce(lambda, &mut bindings(8), 50);
         assert_eq!(reduced, Lambda::def(1, Lambda::var(1), None))
     }
 
+    /// Reduces `lambda` to normal form, contracting the redex `choose` picks from the
+    /// number of redexes.
+    fn reduce_choosing(
+        lambda: Lambda,
+        bindings: &mut Vec<String>,
+        mut choose: impl FnMut(usize) -> usize,
+    ) -> Lambda {
+        let mut arena = Arena::default();
+        let mut term = arena.insert(&lambda);
+        for _ in 0..10000 {
+            let count = arena.redexes(term);
+            if count == 0 {
+                return arena.to_lambda(term);
+            }
+            term = reduce_nth(&mut arena, term, &mut choose(count), bindings).unwrap();
+        }
+        panic!("Term was not reducible in 10000 iterations");
+    }
+
+    fn simply_typed_terms(seed: u64) -> Generator {
+        let mut generator = Generator::new(seed, vec![0, 1, 2, 3]);
+        generator.free = vec![4, 5];
+        generator.simply_typed = true;
+        generator
+    }
+
+    #[test]
+    fn redexes_are_counted_in_order() {
+        // 0(0.(3(3).(4(4).5))).(1(1).2): the body comes before the parameter
+        let parameter = Lambda::def(1, Lambda::var(1), Some(Lambda::var(2)));
+        let inner = Lambda::def(4, Lambda::var(4), Some(Lambda::var(5)));
+        let argument = Lambda::def(3, Lambda::var(3), Some(inner));
+        let lambda = Lambda::def(0, Lambda::call(0, vec![argument.clone()]), Some(parameter));
+        let mut arena = Arena::default();
+        let term = arena.insert(&lambda);
+        assert_eq!(arena.redexes(term), 4);
+        let mut index = 5;
+        assert_eq!(
+            reduce_nth(&mut arena, term, &mut index, &mut bindings(6)),
+            Err(term)
+        );
+        assert_eq!(index, 1);
+        let third = reduce_nth(&mut arena, term, &mut 2, &mut bindings(6)).unwrap();
+        let argument_reduced = Lambda::def(3, Lambda::var(3), Some(Lambda::var(5)));
+        assert_eq!(
+            arena.to_lambda(third),
+            Lambda::def(
+                0,
+                Lambda::call(0, vec![argument_reduced]),
+                Some(Lambda::def(1, Lambda::var(1), Some(Lambda::var(2))))
+            )
+        );
+        let first = find_reducible(&mut arena, term, &mut bindings(6)).unwrap();
+        assert_eq!(
+            arena.to_lambda(first),
+            Lambda::def(1, Lambda::call(1, vec![argument]), Some(Lambda::var(2)))
+        );
+    }
+
+    #[test]
+    fn arguments_are_shared() {
+        // the numeral is used three times, but stored once
+        let numeral = format!("f(x({}x{}))", "f.(".repeat(1000), ")".repeat(1000));
+        let (lambda, mut bindings) = parse_program(&format!("n(g(g.n.n.n)).({numeral})")).unwrap();
+        let mut arena = Arena::default();
+        let term = arena.insert(&lambda);
+        let stored = arena.len();
+        let reduced = find_reducible(&mut arena, term, &mut bindings).unwrap();
+        assert!(arena.size(reduced) > 3000);
+        assert!(arena.len() < stored + 5);
+    }
+
+    #[test]
+    fn strategies_agree() {
+        let mut generator = simply_typed_terms(13);
+        for _ in 0..300 {
+            let lambda = generator.term();
+            let mut normal_bindings = bindings(6);
+            let normal_order = full_reduce(lambda.clone(), &mut normal_bindings, 10000);
+            // the last redex contains no other redex, so this is an innermost strategy
+            let mut innermost_bindings = bindings(6);
+            let innermost = reduce_choosing(lambda, &mut innermost_bindings, |count| count - 1);
+            assert!(alpha_equivalent(
+                &normal_order,
+                &normal_bindings,
+                &innermost,
+                &innermost_bindings
+            ));
+        }
+    }
+
+    #[test]
+    fn random_redex_choices_are_confluent() {
+        let mut generator = simply_typed_terms(17);
+        let mut rng = Rng::new(19);
+        for _ in 0..300 {
+            let lambda = generator.term();
+            let mut normal_bindings = bindings(6);
+            let normal_order = full_reduce(lambda.clone(), &mut normal_bindings, 10000);
+            for _ in 0..3 {
+                let mut random_bindings = bindings(6);
+                let random = reduce_choosing(lambda.clone(), &mut random_bindings, |count| {
+                    rng.below(count)
+                });
+                assert!(alpha_equivalent(
+                    &normal_order,
+                    &normal_bindings,
+                    &random,
+                    &random_bindings
+                ));
+            }
+        }
+    }
+
     // #[test]
     // fn nested_within_itself() {
     //     // f(f(f).a(a)).f(f.5) => f(f.5)(f(f.5)).a(a)
@@ -261,4 +665,13 @@ mod tests {
     //     );
     //     let reduced
     // }
+
+    #[test]
+    fn deep_numeral_is_reduced() {
+        let numeral = format!("f(x({}x{}))", "f.(".repeat(100_000), ")".repeat(100_000));
+        let (lambda, mut bindings) =
+            parse_program(&format!("n(f(x(f.(n.f.x)))).({numeral})")).unwrap();
+        let result = full_reduce(lambda, &mut bindings, 10);
+        assert_eq!(decode_numeral(&result), Some(100_001));
+    }
 }
@@ -0,0 +1,510 @@
+//! Translation of terms into combinators by bracket abstraction and a graph reducer for them.
+
+use std::collections::HashSet;
+
+use crate::Lambda;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Combinator {
+    /// `S f g x = f x (g x)`
+    S,
+    /// `K x y = x`
+    K,
+    /// `I x = x`
+    I,
+    /// `B f g x = f (g x)`
+    B,
+    /// `C f g x = f x g`
+    C,
+    /// `W f x = f x x`
+    W,
+}
+
+impl Combinator {
+    fn arity(self) -> usize {
+        match self {
+            Combinator::I => 1,
+            Combinator::K | Combinator::W => 2,
+            Combinator::S | Combinator::B | Combinator::C => 3,
+        }
+    }
+}
+
+/// Which combinators bracket abstraction may produce.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Basis {
+    Ski,
+    /// Turner's additional combinators, which keep the output much smaller.
+    Skibcw,
+}
+
+#[derive(Debug)]
+pub(crate) enum Expr {
+    Combinator(Combinator),
+    /// A free variable of the translated term.
+    Variable(usize),
+    Application(Box<Expr>, Box<Expr>),
+}
+
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let mut stack = Vec::new();
+        if let Expr::Application(function, argument) = self {
+            stack.push(std::mem::replace(
+                function.as_mut(),
+                Expr::Combinator(Combinator::I),
+            ));
+            stack.push(std::mem::replace(
+                argument.as_mut(),
+                Expr::Combinator(Combinator::I),
+            ));
+        }
+        while let Some(expr) = stack.pop() {
+            if let Ok((function, argument)) = expr.into_application() {
+                stack.push(function);
+                stack.push(argument);
+            }
+        }
+    }
+}
+
+impl Expr {
+    fn apply(function: Expr, argument: Expr) -> Self {
+        Expr::Application(Box::new(function), Box::new(argument))
+    }
+
+    /// Takes the function and argument out of an application, as `Expr` implements `Drop`.
+    fn into_application(mut self) -> Result<(Expr, Expr), Expr> {
+        if let Expr::Application(function, argument) = &mut self {
+            let function = std::mem::replace(function.as_mut(), Expr::Combinator(Combinator::I));
+            let argument = std::mem::replace(argument.as_mut(), Expr::Combinator(Combinator::I));
+            return Ok((function, argument));
+        }
+        Err(self)
+    }
+}
+
+/// A part of the expression a variable is abstracted from: abstracted if the variable occurs
+/// in it, otherwise left as it was.
+struct Abstracted {
+    occurs: bool,
+    /// whether the part is the variable itself
+    is_name: bool,
+    expr: Expr,
+}
+
+impl Abstracted {
+    fn occurring(expr: Expr) -> Self {
+        Abstracted {
+            occurs: true,
+            is_name: false,
+            expr,
+        }
+    }
+
+    /// The abstraction of the part, which ignores its argument if the variable does not occur.
+    fn into_expr(self) -> Expr {
+        if self.occurs {
+            self.expr
+        } else {
+            Expr::apply(Expr::Combinator(Combinator::K), self.expr)
+        }
+    }
+}
+
+fn abstract_application(function: Abstracted, argument: Abstracted, basis: Basis) -> Abstracted {
+    let with = |combinator, function, argument| {
+        Abstracted::occurring(Expr::apply(
+            Expr::apply(Expr::Combinator(combinator), function),
+            argument,
+        ))
+    };
+    let skibcw = basis == Basis::Skibcw;
+    match (function.occurs, argument.occurs) {
+        (false, false) => Abstracted {
+            occurs: false,
+            is_name: false,
+            expr: Expr::apply(function.expr, argument.expr),
+        },
+        (false, true) if argument.is_name => Abstracted::occurring(function.expr),
+        (false, true) if skibcw => with(Combinator::B, function.expr, argument.expr),
+        (true, true) if skibcw && argument.is_name => {
+            Abstracted::occurring(Expr::apply(Expr::Combinator(Combinator::W), function.expr))
+        }
+        (true, false) if skibcw => with(Combinator::C, function.expr, argument.expr),
+        _ => with(Combinator::S, function.into_expr(), argument.into_expr()),
+    }
+}
+
+fn abstract_variable(name: usize, expr: Expr, basis: Basis) -> Expr {
+    enum Work {
+        Visit(Expr),
+        Application,
+    }
+    // bottom up, so that every part is only looked at once to find whether `name` occurs in it
+    let mut work = vec![Work::Visit(expr)];
+    let mut done: Vec<Abstracted> = Vec::new();
+    while let Some(item) = work.pop() {
+        match item {
+            Work::Visit(expr) => match expr.into_application() {
+                Ok((function, argument)) => {
+                    work.push(Work::Application);
+                    work.push(Work::Visit(argument));
+                    work.push(Work::Visit(function));
+                }
+                Err(Expr::Variable(value)) if value == name => done.push(Abstracted {
+                    occurs: true,
+                    is_name: true,
+                    expr: Expr::Combinator(Combinator::I),
+                }),
+                Err(expr) => done.push(Abstracted {
+                    occurs: false,
+                    is_name: false,
+                    expr,
+                }),
+            },
+            Work::Application => {
+                let argument = done.pop().unwrap();
+                let function = done.pop().unwrap();
+                done.push(abstract_application(function, argument, basis));
+            }
+        }
+    }
+    done.pop().unwrap().into_expr()
+}
+
+/// Translates `lambda` into combinators. Free variables are kept as variables.
+pub(crate) fn lambda_to_combinators(lambda: &Lambda, basis: Basis) -> Expr {
+    enum Work<'a> {
+        Visit(&'a Lambda),
+        Definition(usize, bool),
+        Call(usize, usize),
+    }
+    let mut work = vec![Work::Visit(lambda)];
+    let mut done = Vec::new();
+    while let Some(item) = work.pop() {
+        match item {
+            Work::Visit(Lambda::Variable(value)) => done.push(Expr::Variable(*value)),
+            Work::Visit(Lambda::Definition {
+                name_index,
+                body,
+                parameter,
+            }) => {
+                work.push(Work::Definition(*name_index, parameter.is_some()));
+                work.push(Work::Visit(body));
+                if let Some(parameter) = parameter {
+                    work.push(Work::Visit(parameter));
+                }
+            }
+            Work::Visit(Lambda::Call {
+                name_index,
+                parameters,
+            }) => {
+                work.push(Work::Call(*name_index, parameters.len()));
+                work.extend(parameters.iter().rev().map(Work::Visit));
+            }
+            Work::Definition(name_index, has_parameter) => {
+                let body = done.pop().unwrap();
+                let function = abstract_variable(name_index, body, basis);
+                let expr = match has_parameter {
+                    true => Expr::apply(function, done.pop().unwrap()),
+                    false => function,
+                };
+                done.push(expr);
+            }
+            Work::Call(name_index, count) => {
+                let parameters = done.split_off(done.len() - count);
+                let expr = parameters
+                    .into_iter()
+                    .fold(Expr::Variable(name_index), Expr::apply);
+                done.push(expr);
+            }
+        }
+    }
+    done.pop().unwrap()
+}
+
+/// Prints combinators with left associative application, e.g. `S (K f) I`.
+pub(crate) fn format_combinators(expr: &Expr, bindings: &[String]) -> String {
+    enum Work<'a> {
+        Visit(&'a Expr),
+        Text(&'static str),
+    }
+    let mut string = String::new();
+    let mut work = vec![Work::Visit(expr)];
+    while let Some(item) = work.pop() {
+        match item {
+            Work::Text(text) => string += text,
+            Work::Visit(Expr::Combinator(combinator)) => string += &format!("{combinator:?}"),
+            Work::Visit(Expr::Variable(value)) => string += &bindings[*value],
+            Work::Visit(Expr::Application(function, argument)) => {
+                match argument.as_ref() {
+                    Expr::Application(..) => {
+                        work.push(Work::Text(")"));
+                        work.push(Work::Visit(argument));
+                        work.push(Work::Text(" ("));
+                    }
+                    _ => {
+                        work.push(Work::Visit(argument));
+                        work.push(Work::Text(" "));
+                    }
+                }
+                work.push(Work::Visit(function));
+            }
+        }
+    }
+    string
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Node {
+    Combinator(Combinator),
+    Variable(usize),
+    Application(usize, usize),
+    /// The node was reduced to the node it points to.
+    Indirection(usize),
+}
+
+/// Combinator graph: reducing a redex overwrites its root node, so shared arguments
+/// are only reduced once.
+struct Graph {
+    nodes: Vec<Node>,
+    steps: usize,
+    iterations: usize,
+}
+
+impl Graph {
+    fn add(&mut self, node: Node) -> usize {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    fn insert(&mut self, expr: &Expr) -> usize {
+        enum Work<'a> {
+            Visit(&'a Expr),
+            Application,
+        }
+        let mut work = vec![Work::Visit(expr)];
+        let mut done = Vec::new();
+        while let Some(item) = work.pop() {
+            let node = match item {
+                Work::Visit(Expr::Combinator(combinator)) => Node::Combinator(*combinator),
+                Work::Visit(Expr::Variable(value)) => Node::Variable(*value),
+                Work::Visit(Expr::Application(function, argument)) => {
+                    work.push(Work::Application);
+                    work.push(Work::Visit(argument));
+                    work.push(Work::Visit(function));
+                    continue;
+                }
+                Work::Application => {
+                    let argument = done.pop().unwrap();
+                    let function = done.pop().unwrap();
+                    Node::Application(function, argument)
+                }
+            };
+            done.push(self.add(node));
+        }
+        done.pop().unwrap()
+    }
+
+    fn follow(&self, mut node: usize) -> usize {
+        while let Node::Indirection(target) = self.nodes[node] {
+            node = target;
+        }
+        node
+    }
+
+    fn argument(&self, application: usize) -> usize {
+        match self.nodes[application] {
+            Node::Application(_, argument) => self.follow(argument),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Reduces `root` to weak head normal form and returns the applications on its spine,
+    /// outermost first.
+    fn weak_head_normalize(&mut self, root: usize) -> Vec<usize> {
+        let mut spine = Vec::new();
+        let mut current = self.follow(root);
+        loop {
+            match self.nodes[current] {
+                Node::Application(function, _) => {
+                    spine.push(current);
+                    current = self.follow(function);
+                }
+                Node::Combinator(combinator) if spine.len() >= combinator.arity() => {
+                    if self.steps == self.iterations {
+                        panic!("Term was not reducible in {} iterations", self.iterations);
+                    }
+                    self.steps += 1;
+                    let arity = combinator.arity();
+                    let redex = spine[spine.len() - arity];
+                    let args: Vec<usize> = spine[spine.len() - arity..]
+                        .iter()
+                        .rev()
+                        .map(|application| self.argument(*application))
+                        .collect();
+                    let reduced = match combinator {
+                        Combinator::I | Combinator::K => Node::Indirection(args[0]),
+                        Combinator::S => {
+                            let function = self.add(Node::Application(args[0], args[2]));
+                            let argument = self.add(Node::Application(args[1], args[2]));
+                            Node::Application(function, argument)
+                        }
+                        Combinator::B => {
+                            let argument = self.add(Node::Application(args[1], args[2]));
+                            Node::Application(args[0], argument)
+                        }
+                        Combinator::C => {
+                            let function = self.add(Node::Application(args[0], args[2]));
+                            Node::Application(function, args[1])
+                        }
+                        Combinator::W => {
+                            let function = self.add(Node::Application(args[0], args[1]));
+                            Node::Application(function, args[1])
+                        }
+                    };
+                    self.nodes[redex] = reduced;
+                    spine.truncate(spine.len() - arity);
+                    current = self.follow(redex);
+                }
+                _ => return spine,
+            }
+        }
+    }
+
+    fn normalize(&mut self, root: usize) {
+        let mut stack = vec![root];
+        let mut normalized = HashSet::new();
+        while let Some(node) = stack.pop() {
+            let node = self.follow(node);
+            if !normalized.insert(node) {
+                continue;
+            }
+            let spine = self.weak_head_normalize(node);
+            stack.extend(spine.iter().map(|application| self.argument(*application)));
+        }
+    }
+
+    fn read_back(&self, node: usize) -> Expr {
+        enum Work {
+            Visit(usize),
+            Application,
+        }
+        let mut work = vec![Work::Visit(node)];
+        let mut done = Vec::new();
+        while let Some(item) = work.pop() {
+            let expr = match item {
+                Work::Visit(node) => match self.nodes[self.follow(node)] {
+                    Node::Combinator(combinator) => Expr::Combinator(combinator),
+                    Node::Variable(value) => Expr::Variable(value),
+                    Node::Application(function, argument) => {
+                        work.push(Work::Application);
+                        work.push(Work::Visit(argument));
+                        work.push(Work::Visit(function));
+                        continue;
+                    }
+                    Node::Indirection(_) => unreachable!(),
+                },
+                Work::Application => {
+                    let argument = done.pop().unwrap();
+                    let function = done.pop().unwrap();
+                    Expr::apply(function, argument)
+                }
+            };
+            done.push(expr);
+        }
+        done.pop().unwrap()
+    }
+}
+
+/// Reduces `expr` to normal form in normal order and returns it with the number of steps taken.
+pub(crate) fn reduce_combinators(expr: &Expr, iterations: usize) -> (Expr, usize) {
+    let mut graph = Graph {
+        nodes: Vec::new(),
+        steps: 0,
+        iterations,
+    };
+    let root = graph.insert(expr);
+    graph.normalize(root);
+    (graph.read_back(root), graph.steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        compiler::compile, helpers::format_lambda, parser::parse_program, reducer::full_reduce,
+    };
+
+    use super::{format_combinators, lambda_to_combinators, reduce_combinators, Basis};
+
+    fn translate(text: &str, basis: Basis) -> String {
+        let (lambda, bindings) = parse_program(&compile(text).unwrap()).unwrap();
+        format_combinators(&lambda_to_combinators(&lambda, basis), &bindings)
+    }
+
+    fn reduce(text: &str, basis: Basis) -> String {
+        let (lambda, bindings) = parse_program(&compile(text).unwrap()).unwrap();
+        let (reduced, _steps) = reduce_combinators(&lambda_to_combinators(&lambda, basis), 10000);
+        format_combinators(&reduced, &bindings)
+    }
+
+    #[test]
+    fn bracket_abstraction() {
+        assert_eq!(translate("x(x)", Basis::Ski), "I");
+        assert_eq!(translate("x(y(x))", Basis::Ski), "K");
+        assert_eq!(translate("f(x(f.x.x))", Basis::Ski), "S S (K I)");
+        assert_eq!(translate("f(x(f.x.x))", Basis::Skibcw), "W");
+        assert_eq!(translate("f(g(x(f.(g.x))))", Basis::Skibcw), "B");
+        assert_eq!(translate("f(x(y(f.y.x)))", Basis::Skibcw), "C");
+    }
+
+    #[test]
+    fn free_variables_are_kept() {
+        assert_eq!(translate("x(y).(z)", Basis::Ski), "K y z");
+        assert_eq!(reduce("x(y).(z)", Basis::Ski), "y");
+    }
+
+    #[test]
+    fn arithmetic_agrees_with_lambda_reducer() {
+        let text = "
+        let add m,n(
+            f,x(
+                (m.f).(n.f.x)
+            )
+        );
+        let mul n,m(
+            f,x(m.(n.f).x)
+        );
+        let succ n,f,x(
+            f.(n.f.x)
+        );
+        let zero f,x(x);
+        let m succ.(succ.(succ.zero));
+        let n succ.(succ.zero);
+        mul.(add.m.n).n.g.y
+        ";
+        let (lambda, mut bindings) = parse_program(&compile(text).unwrap()).unwrap();
+        let expected = format_lambda(&full_reduce(lambda, &mut bindings, 10000), &bindings);
+        assert_eq!(expected, "g.(g.(g.(g.(g.(g.(g.(g.(g.(g.(y))))))))))");
+        let expected = "g (g (g (g (g (g (g (g (g (g y)))))))))";
+        assert_eq!(reduce(text, Basis::Ski), expected);
+        assert_eq!(reduce(text, Basis::Skibcw), expected);
+    }
+
+    #[test]
+    fn shared_arguments_are_reduced_once() {
+        // W duplicates the argument, which is reduced in 3 steps only once
+        let (lambda, _bindings) =
+            parse_program(&compile("a(b(a.b.b)).(f(f)).(i(i).(j(j)).y)").unwrap()).unwrap();
+        let expr = lambda_to_combinators(&lambda, Basis::Skibcw);
+        let (_reduced, steps) = reduce_combinators(&expr, 100);
+        assert_eq!(steps, 5);
+    }
+
+    #[test]
+    #[should_panic]
+    fn omega() {
+        reduce("x(x.x).(x(x.x))", Basis::Ski);
+    }
+}
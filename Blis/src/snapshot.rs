@@ -0,0 +1,157 @@
+//! `blis snapshot`: compares the output of programs with the `.expected` files next to them,
+//! so that changes to the reducer or the printer that alter a result are noticed.
+
+use std::{fs, io, path::Path};
+
+use crate::{
+    assertions::{collect_files, diff},
+    run, Options,
+};
+
+/// The output of the program `contents` of `file` as printed by `blis`, or the error it
+/// fails with. Errors are part of the snapshot, so that programs without normal form can be
+/// kept too.
+fn output(file: &str, contents: Vec<u8>, options: &Options) -> String {
+    match run(file, contents, options, &mut io::sink()) {
+        Ok(output) => output,
+        Err(error) => format!("error: {error}\n"),
+    }
+}
+
+fn expected_path(file: &str) -> String {
+    Path::new(file)
+        .with_extension("expected")
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Compares the output of the programs in `paths`, run with `options`, with their snapshots,
+/// or writes the snapshots if `bless` is set. Returns whether all of them matched.
+pub(crate) fn run_snapshots(paths: &[String], bless: bool, options: &Options) -> bool {
+    let files = match collect_files(paths) {
+        Ok(files) => files,
+        Err(error) => {
+            println!("{error}");
+            return false;
+        }
+    };
+    let (mut matched, mut failed) = (0, 0);
+    for file in files {
+        let actual = match fs::read(&file) {
+            Ok(contents) => output(&file, contents, options),
+            Err(error) => {
+                println!("{file}: FAILED\n    Error reading file: {error}");
+                failed += 1;
+                continue;
+            }
+        };
+        let snapshot = expected_path(&file);
+        let expected = fs::read_to_string(&snapshot).ok();
+        if expected.as_deref() == Some(actual.as_str()) {
+            matched += 1;
+            continue;
+        }
+        if bless {
+            match fs::write(&snapshot, &actual) {
+                Ok(()) => {
+                    println!("{file}: wrote {snapshot}");
+                    matched += 1;
+                }
+                Err(error) => {
+                    println!("{file}: FAILED\n    Error writing {snapshot}: {error}");
+                    failed += 1;
+                }
+            }
+            continue;
+        }
+        failed += 1;
+        match expected {
+            None => println!("{file}: FAILED\n    {snapshot} is missing, run with --bless"),
+            Some(expected) => println!(
+                "{file}: FAILED, expected (-) and actual (+):\n{}",
+                diff(&expected, &actual)
+            ),
+        }
+    }
+    let result = if failed == 0 { "ok" } else { "FAILED" };
+    println!("snapshot result: {result}. {matched} matched; {failed} failed");
+    failed == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{Emit, Options};
+
+    use super::{expected_path, output, run_snapshots};
+
+    fn options() -> Options {
+        Options {
+            cache: None,
+            ..Options::default()
+        }
+    }
+
+    fn text_output(text: &str) -> String {
+        output("test.txt", text.as_bytes().to_vec(), &options())
+    }
+
+    #[test]
+    fn outputs() {
+        assert_eq!(text_output("let id x(x); id.\"ok\""), "\"ok\"\n");
+        assert_eq!(text_output("f(f.y).x(x)"), "y\n");
+        assert_eq!(
+            text_output("x(x.x).(x(x.x))"),
+            "error: not reducible in 10000 iterations\n"
+        );
+        assert_eq!(
+            text_output("foo.x"),
+            "error: undefined name `foo` at line 1\n"
+        );
+    }
+
+    #[test]
+    fn outputs_follow_the_options() {
+        let blc = Options {
+            emit: Emit::Blc,
+            ..options()
+        };
+        assert_eq!(output("id.txt", b"x(x)".to_vec(), &blc), "0010\n");
+        let typed = Options {
+            typecheck: true,
+            ..options()
+        };
+        assert_eq!(
+            output("omega.txt", b"x(x.x)".to_vec(), &typed),
+            "error: type error in `x.(x)`: `a` would have the infinite type `a -> b`\n"
+        );
+        assert!(
+            output("id.json", b"{}".to_vec(), &options()).starts_with("error: invalid JSON term")
+        );
+    }
+
+    #[test]
+    fn bless_then_compare() {
+        let directory = std::env::temp_dir().join(format!("blis-snapshot-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let file = directory.join("id.txt").to_string_lossy().into_owned();
+        fs::write(&file, "let id x(x); id.y").unwrap();
+        let paths = [directory.to_string_lossy().into_owned()];
+
+        assert!(!run_snapshots(&paths, false, &options()));
+        assert!(run_snapshots(&paths, true, &options()));
+        assert_eq!(fs::read_to_string(expected_path(&file)).unwrap(), "y\n");
+        assert!(run_snapshots(&paths, false, &options()));
+        fs::write(&file, "let id x(x); id.z").unwrap();
+        assert!(!run_snapshots(&paths, false, &options()));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn examples_match_their_snapshots() {
+        let examples = concat!(env!("CARGO_MANIFEST_DIR"), "/../examples");
+        assert!(run_snapshots(&[examples.to_string()], false, &options()));
+    }
+}
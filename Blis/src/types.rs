@@ -0,0 +1,599 @@
+//! Hindley-Milner type inference for simple types.
+//! Every applied definition `x(body).(value)` is typed like `let x = value in body`,
+//! so definitions introduced by `let` can be used polymorphically.
+
+use std::collections::HashMap;
+
+use crate::{helpers::format_lambda, Lambda};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Type {
+    Variable(usize),
+    Function(Box<Type>, Box<Type>),
+}
+
+impl Type {
+    pub(crate) fn function(argument: Type, result: Type) -> Self {
+        Type::Function(Box::new(argument), Box::new(result))
+    }
+}
+
+/// Prints type variables as `a`, `b`, ... in the order they appear.
+pub(crate) fn format_type(t: &Type) -> String {
+    format_types(&[t]).pop().unwrap()
+}
+
+/// Like `format_type`, but the same variable gets the same name in all types.
+fn format_types(types: &[&Type]) -> Vec<String> {
+    let mut names = Vec::new();
+    types
+        .iter()
+        .map(|t| {
+            let mut string = String::new();
+            write_type(t, &mut string, &mut |variable| {
+                let number = match names.iter().position(|v| *v == variable) {
+                    Some(number) => number,
+                    None => {
+                        names.push(variable);
+                        names.len() - 1
+                    }
+                };
+                let mut name = ((b'a' + (number % 26) as u8) as char).to_string();
+                if number >= 26 {
+                    name += &(number / 26).to_string();
+                }
+                name
+            });
+            string
+        })
+        .collect()
+}
+
+fn write_type(t: &Type, string: &mut String, name: &mut impl FnMut(usize) -> String) {
+    match t {
+        Type::Variable(variable) => *string += &name(*variable),
+        Type::Function(argument, result) => {
+            if let Type::Function(..) = argument.as_ref() {
+                string.push('(');
+                write_type(argument, string, name);
+                string.push(')');
+            } else {
+                write_type(argument, string, name);
+            }
+            *string += " -> ";
+            write_type(result, string, name);
+        }
+    }
+}
+
+/// Type annotations of one definition. The variables of `types` index into `names`,
+/// named variables are rigid, unnamed ones (`None`) are placeholders for unannotated parts.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Annotation {
+    pub(crate) definition: String,
+    pub(crate) types: Vec<Type>,
+    pub(crate) names: Vec<Option<String>>,
+}
+
+impl Annotation {
+    fn format(&self) -> String {
+        let types: Vec<String> = self
+            .types
+            .iter()
+            .map(|t| {
+                let mut string = String::new();
+                write_type(t, &mut string, &mut |variable| {
+                    self.names[variable].clone().unwrap_or("_".to_string())
+                });
+                string
+            })
+            .collect();
+        types.join("` and `")
+    }
+}
+
+fn is_type_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '\''
+}
+
+/// Parses the type at the start of `text`, with `type ::= name | ( type ) | type -> type`,
+/// and returns it with the number of bytes it spans. Names of `aliases` are replaced
+/// by their definition, other names starting with an uppercase letter are unknown.
+pub(crate) fn parse_type_prefix(
+    text: &str,
+    aliases: &HashMap<String, String>,
+    names: &mut Vec<Option<String>>,
+) -> Result<(Type, usize), String> {
+    let start = text.len() - text.trim_start().len();
+    let rest = &text[start..];
+    let (argument, argument_end) = if let Some(inner) = rest.strip_prefix('(') {
+        let (t, end) = parse_type_prefix(inner, aliases, names)?;
+        let after = &inner[end..];
+        let close = after.len() - after.trim_start().len();
+        if !after[close..].starts_with(')') {
+            return Err(format!("expected `)` in type `{}`", text.trim()));
+        }
+        (t, start + 1 + end + close + 1)
+    } else {
+        let name_end = rest
+            .find(|c: char| !is_type_name_char(c))
+            .unwrap_or(rest.len());
+        let name = &rest[..name_end];
+        if name.is_empty() {
+            return Err(format!("expected a type in `{}`", text.trim()));
+        }
+        let t = if let Some(alias) = aliases.get(name) {
+            parse_type(alias, aliases, names)?
+        } else if name.starts_with(|c: char| c.is_uppercase()) {
+            return Err(format!("unknown type `{name}`"));
+        } else {
+            let variable = match names.iter().position(|n| n.as_deref() == Some(name)) {
+                Some(variable) => variable,
+                None => {
+                    names.push(Some(name.to_owned()));
+                    names.len() - 1
+                }
+            };
+            Type::Variable(variable)
+        };
+        (t, start + name_end)
+    };
+    let after = &text[argument_end..];
+    let arrow = after.len() - after.trim_start().len();
+    if let Some(result) = after[arrow..].strip_prefix("->") {
+        let (result, result_end) = parse_type_prefix(result, aliases, names)?;
+        let end = argument_end + arrow + 2 + result_end;
+        return Ok((Type::function(argument, result), end));
+    }
+    Ok((argument, argument_end))
+}
+
+/// Parses `text`, which has to consist of exactly one type.
+pub(crate) fn parse_type(
+    text: &str,
+    aliases: &HashMap<String, String>,
+    names: &mut Vec<Option<String>>,
+) -> Result<Type, String> {
+    let (t, end) = parse_type_prefix(text, aliases, names)?;
+    if !text[end..].trim().is_empty() {
+        return Err(format!("unexpected `{}` after type", text[end..].trim()));
+    }
+    Ok(t)
+}
+
+#[derive(Debug, Clone)]
+struct Scheme {
+    quantified: Vec<usize>,
+    body: Type,
+}
+
+pub(crate) struct Inference<'a> {
+    bindings: &'a [String],
+    /// the type every type variable was unified with
+    substitution: Vec<Option<Type>>,
+    /// the let-nesting level every type variable was created at, used for generalization
+    levels: Vec<usize>,
+    level: usize,
+    scope: Vec<(usize, Scheme)>,
+    free: HashMap<usize, Type>,
+}
+
+impl<'a> Inference<'a> {
+    pub(crate) fn new(bindings: &'a [String]) -> Self {
+        Inference {
+            bindings,
+            substitution: Vec::new(),
+            levels: Vec::new(),
+            level: 0,
+            scope: Vec::new(),
+            free: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn fresh(&mut self) -> Type {
+        self.substitution.push(None);
+        self.levels.push(self.level);
+        Type::Variable(self.substitution.len() - 1)
+    }
+
+    /// Replaces all unified type variables in `t`.
+    pub(crate) fn resolve(&self, t: &Type) -> Type {
+        let mut t = t;
+        while let Type::Variable(variable) = t {
+            match &self.substitution[*variable] {
+                Some(unified) => t = unified,
+                None => return t.clone(),
+            }
+        }
+        let Type::Function(argument, result) = t else {
+            unreachable!()
+        };
+        Type::function(self.resolve(argument), self.resolve(result))
+    }
+
+    /// Replaces the unified type variables at the top of `t`. The variables on the way point
+    /// to the result afterwards, as unifying long chains of variables, which deep terms like
+    /// numerals create, would otherwise follow the whole chain every time.
+    fn shallow_resolve(&mut self, t: &Type) -> Type {
+        let mut chain = Vec::new();
+        let mut t = t.clone();
+        while let Type::Variable(variable) = t {
+            match &self.substitution[variable] {
+                Some(unified) => {
+                    chain.push(variable);
+                    t = unified.clone();
+                }
+                None => break,
+            }
+        }
+        if chain.len() > 1 {
+            for variable in chain {
+                self.substitution[variable] = Some(t.clone());
+            }
+        }
+        t
+    }
+
+    fn occurs(&mut self, variable: usize, t: &Type) -> bool {
+        match self.shallow_resolve(t) {
+            Type::Variable(other) => {
+                // the variable may end up in an outer let, so it must not be generalized there
+                self.levels[other] = self.levels[other].min(self.levels[variable]);
+                other == variable
+            }
+            Type::Function(argument, result) => {
+                self.occurs(variable, &argument) || self.occurs(variable, &result)
+            }
+        }
+    }
+
+    pub(crate) fn unify(&mut self, a: &Type, b: &Type) -> Result<(), String> {
+        let a = self.shallow_resolve(a);
+        let b = self.shallow_resolve(b);
+        match (&a, &b) {
+            (Type::Variable(a), Type::Variable(b)) if a == b => Ok(()),
+            (Type::Variable(variable), t) | (t, Type::Variable(variable)) => {
+                if self.occurs(*variable, t) {
+                    let [variable, t] = format_types(&[&Type::Variable(*variable), t])
+                        .try_into()
+                        .unwrap();
+                    return Err(format!("`{variable}` would have the infinite type `{t}`"));
+                }
+                self.substitution[*variable] = Some(t.clone());
+                Ok(())
+            }
+            (Type::Function(a_argument, a_result), Type::Function(b_argument, b_result)) => {
+                self.unify(a_argument, b_argument)?;
+                self.unify(a_result, b_result)
+            }
+        }
+    }
+
+    fn generalize(&self, t: &Type) -> Scheme {
+        fn collect(inference: &Inference, t: &Type, quantified: &mut Vec<usize>) {
+            match t {
+                Type::Variable(variable) => {
+                    if inference.levels[*variable] > inference.level
+                        && !quantified.contains(variable)
+                    {
+                        quantified.push(*variable);
+                    }
+                }
+                Type::Function(argument, result) => {
+                    collect(inference, argument, quantified);
+                    collect(inference, result, quantified);
+                }
+            }
+        }
+        let body = self.resolve(t);
+        let mut quantified = Vec::new();
+        collect(self, &body, &mut quantified);
+        Scheme { quantified, body }
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        fn replace(t: &Type, fresh: &HashMap<usize, Type>) -> Type {
+            match t {
+                Type::Variable(variable) => fresh.get(variable).cloned().unwrap_or(t.clone()),
+                Type::Function(argument, result) => {
+                    Type::function(replace(argument, fresh), replace(result, fresh))
+                }
+            }
+        }
+        let fresh = scheme
+            .quantified
+            .iter()
+            .map(|variable| (*variable, self.fresh()))
+            .collect();
+        replace(&scheme.body, &fresh)
+    }
+
+    fn variable(&mut self, index: usize) -> Type {
+        let scheme = self.scope.iter().rev().find(|(i, _)| *i == index);
+        if let Some((_, scheme)) = scheme {
+            let scheme = scheme.clone();
+            return self.instantiate(&scheme);
+        }
+        if let Some(t) = self.free.get(&index) {
+            return t.clone();
+        }
+        // free variables are shared by the whole program, so they are never generalized
+        let t = self.fresh();
+        *self.levels.last_mut().unwrap() = 0;
+        self.free.insert(index, t.clone());
+        t
+    }
+
+    /// Infers the type of `value` and generalizes it, as for the value of a `let`.
+    fn infer_generalized(&mut self, value: &Lambda) -> Result<Scheme, String> {
+        self.level += 1;
+        let t = self.infer(value);
+        self.level -= 1;
+        Ok(self.generalize(&t?))
+    }
+
+    /// The scheme of a value that can be used as any type.
+    fn any(&mut self) -> Scheme {
+        self.level += 1;
+        let t = self.fresh();
+        self.level -= 1;
+        self.generalize(&t)
+    }
+
+    /// Checks that `scheme` is at least as general as all types of `annotation`
+    /// and returns the annotated type.
+    fn check_annotation(
+        &mut self,
+        scheme: &Scheme,
+        annotation: &Annotation,
+    ) -> Result<Scheme, String> {
+        let mismatch = || {
+            format!(
+                "definition `{}` has type `{}`, which does not match its annotation `{}`",
+                annotation.definition,
+                format_type(&scheme.body),
+                annotation.format()
+            )
+        };
+        self.level += 1;
+        let inferred = self.instantiate(scheme);
+        let variables: Vec<Type> = annotation.names.iter().map(|_| self.fresh()).collect();
+        let mut unified = Ok(());
+        for t in &annotation.types {
+            let expected = substitute(t, &variables);
+            unified = unified.and_then(|_| self.unify(&inferred, &expected));
+        }
+        self.level -= 1;
+        unified.map_err(|_| mismatch())?;
+        // named variables must stay distinct variables that are not fixed outside the definition
+        let mut rigid = Vec::new();
+        for (variable, name) in variables.iter().zip(&annotation.names) {
+            if name.is_none() {
+                continue;
+            }
+            match self.resolve(variable) {
+                Type::Variable(v) if self.levels[v] > self.level && !rigid.contains(&v) => {
+                    rigid.push(v)
+                }
+                _ => return Err(mismatch()),
+            }
+        }
+        Ok(self.generalize(&inferred))
+    }
+
+    /// Infers the type of `lambda`, leaving the scope and level as they were also on errors.
+    pub(crate) fn infer(&mut self, lambda: &Lambda) -> Result<Type, String> {
+        let (scope, level) = (self.scope.len(), self.level);
+        let result = self.infer_with_stack(lambda);
+        self.scope.truncate(scope);
+        self.level = level;
+        result
+    }
+
+    fn infer_with_stack(&mut self, lambda: &Lambda) -> Result<Type, String> {
+        enum Work<'l> {
+            Visit(&'l Lambda),
+            /// the end of the body of a definition that is not applied
+            Abstraction(Type),
+            /// the end of the parameter of an applied definition, before its body
+            Let(usize, &'l Lambda),
+            /// the end of the body of an applied definition
+            EndLet,
+            /// the end of a parameter of the call
+            Apply(&'l Lambda),
+        }
+        let mut work = vec![Work::Visit(lambda)];
+        let mut types = Vec::new();
+        while let Some(item) = work.pop() {
+            match item {
+                Work::Visit(Lambda::Variable(value)) => types.push(self.variable(*value)),
+                Work::Visit(Lambda::Definition {
+                    name_index,
+                    body,
+                    parameter: None,
+                }) => {
+                    let argument = self.fresh();
+                    self.scope.push((
+                        *name_index,
+                        Scheme {
+                            quantified: Vec::new(),
+                            body: argument.clone(),
+                        },
+                    ));
+                    work.push(Work::Abstraction(argument));
+                    work.push(Work::Visit(body));
+                }
+                Work::Visit(Lambda::Definition {
+                    name_index,
+                    body,
+                    parameter: Some(parameter),
+                }) => {
+                    // the parameter is generalized, as the value of a `let`
+                    self.level += 1;
+                    work.push(Work::Let(*name_index, body));
+                    work.push(Work::Visit(parameter));
+                }
+                Work::Visit(
+                    call @ Lambda::Call {
+                        name_index,
+                        parameters,
+                    },
+                ) => {
+                    types.push(self.variable(*name_index));
+                    for parameter in parameters.iter().rev() {
+                        work.push(Work::Apply(call));
+                        work.push(Work::Visit(parameter));
+                    }
+                }
+                Work::Abstraction(argument) => {
+                    self.scope.pop();
+                    let result = types.pop().unwrap();
+                    types.push(Type::function(argument, result));
+                }
+                Work::Let(name_index, body) => {
+                    self.level -= 1;
+                    let scheme = self.generalize(&types.pop().unwrap());
+                    self.scope.push((name_index, scheme));
+                    work.push(Work::EndLet);
+                    work.push(Work::Visit(body));
+                }
+                Work::EndLet => {
+                    self.scope.pop();
+                }
+                Work::Apply(call) => {
+                    let argument = types.pop().unwrap();
+                    let function = types.pop().unwrap();
+                    let result = self.fresh();
+                    let expected = Type::function(argument, result.clone());
+                    self.unify(&function, &expected).map_err(|error| {
+                        format!(
+                            "type error in `{}`: {error}",
+                            format_lambda(call, self.bindings)
+                        )
+                    })?;
+                    types.push(result);
+                }
+            }
+        }
+        Ok(types.pop().unwrap())
+    }
+}
+
+fn substitute(t: &Type, variables: &[Type]) -> Type {
+    match t {
+        Type::Variable(variable) => variables[*variable].clone(),
+        Type::Function(argument, result) => Type::function(
+            substitute(argument, variables),
+            substitute(result, variables),
+        ),
+    }
+}
+
+/// Checks the values of the annotated definitions at the root of `lambda` against their
+/// annotations, which are given by the index of the definition's binder. Other values are
+/// only inferred for the uses in annotated ones, a value without a type can be any type.
+pub(crate) fn check_annotations(
+    lambda: &Lambda,
+    bindings: &[String],
+    annotations: &HashMap<usize, Annotation>,
+) -> Result<(), String> {
+    let mut inference = Inference::new(bindings);
+    let mut current = lambda;
+    while let Lambda::Definition {
+        name_index,
+        body,
+        parameter: Some(parameter),
+    } = current
+    {
+        let scheme = match annotations.get(name_index) {
+            Some(annotation) => {
+                let scheme = inference.infer_generalized(parameter)?;
+                inference.check_annotation(&scheme, annotation)?
+            }
+            None => match inference.infer_generalized(parameter) {
+                Ok(scheme) => scheme,
+                Err(_) => inference.any(),
+            },
+        };
+        inference.scope.push((*name_index, scheme));
+        current = body;
+    }
+    Ok(())
+}
+
+/// Infers the most general type of `lambda`, or describes the first subterm that has no type.
+pub(crate) fn infer_type(lambda: &Lambda, bindings: &[String]) -> Result<Type, String> {
+    let mut inference = Inference::new(bindings);
+    let t = inference.infer(lambda)?;
+    Ok(inference.resolve(&t))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compiler::compile, parser::parse_program};
+
+    use super::{format_type, infer_type};
+
+    fn infer(text: &str) -> Result<String, String> {
+        let (lambda, bindings) = parse_program(&compile(text).unwrap()).unwrap();
+        infer_type(&lambda, &bindings).map(|t| format_type(&t))
+    }
+
+    #[test]
+    fn identity() {
+        assert_eq!(infer("x(x)"), Ok("a -> a".to_string()));
+    }
+
+    #[test]
+    fn church_numeral() {
+        assert_eq!(infer("f,x(f.(f.x))"), Ok("(a -> a) -> a -> a".to_string()));
+    }
+
+    #[test]
+    fn free_variables() {
+        assert_eq!(infer("f(f.y.y)"), Ok("(a -> a -> b) -> b".to_string()));
+    }
+
+    #[test]
+    fn let_polymorphism() {
+        assert_eq!(infer("let id x(x); id.id"), Ok("a -> a".to_string()));
+    }
+
+    #[test]
+    fn lambda_bound_variables_are_monomorphic() {
+        assert_eq!(
+            infer("g(x(x.x))"),
+            Err("type error in `x.(x)`: `a` would have the infinite type `a -> b`".to_string())
+        );
+    }
+
+    #[test]
+    fn numeral_used_as_boolean() {
+        let text = "
+        let two f,x(f.(f.x));
+        let true a,b(a);
+        two.true.y.z
+        ";
+        let error = infer(text).unwrap_err();
+        assert!(error.starts_with("type error in `two.(true)"), "{error}");
+    }
+
+    #[test]
+    fn arithmetic() {
+        let text = "
+        let add m,n(
+            f,x(
+                (m.f).(n.f.x)
+            )
+        );
+        let succ n,f,x(
+            f.(n.f.x)
+        );
+        let zero f,x(x);
+        let m succ.(succ.(succ.zero));
+        let n succ.(succ.zero);
+        add.m.n
+        ";
+        assert_eq!(infer(text), Ok("(a -> a) -> a -> a".to_string()));
+    }
+}